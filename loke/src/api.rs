@@ -1,6 +1,19 @@
-use crate::model::{self, Action, Award, Map, Personality, Proposal, Request, Response, Score};
+use crate::{
+    cache::EvaluationCache,
+    fixture::FixtureRecorder,
+    model::{self, Action, Award, GameRules, Map, Personality, Proposal, Request, Response, Score},
+};
 use reqwest::StatusCode;
-use std::{cell::Cell, collections::BTreeMap, fmt, fs, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 use tokio::time::Instant;
 
 #[derive(Clone, Debug)]
@@ -15,65 +28,222 @@ pub struct InputData {
     pub awards: BTreeMap<&'static str, Award>,
     pub personalities: BTreeMap<String, Personality>,
     pub map: Map,
+    pub rules: GameRules,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    MissingFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    UnknownPersonality {
+        personality: String,
+    },
+    /// Personality names are case insensitive
+    DuplicatePersonality {
+        personality: String,
+    },
+    DuplicateCustomer {
+        customer: &'static str,
+    },
+    UndefinedPersonality {
+        customer: &'static str,
+        personality: String,
+    },
+    AwardCount {
+        count: usize,
+    },
+    /// Counts and intervals of `GameRules` that the simulation divides by or counts down from
+    ZeroRule {
+        rule: &'static str,
+    },
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::MissingFile { path, source } => write!(f, "{}: {source}", path.display()),
+            // `source` ends with the line and column
+            LoadError::Json { path, source } => write!(f, "{}: {source}", path.display()),
+            LoadError::UnknownPersonality { personality } => {
+                write!(f, "unknown personality {personality:?}")
+            }
+            LoadError::DuplicatePersonality { personality } => {
+                write!(f, "personality {personality:?} occurs more than once")
+            }
+            LoadError::DuplicateCustomer { customer } => {
+                write!(f, "customer {customer:?} occurs more than once")
+            }
+            LoadError::UndefinedPersonality {
+                customer,
+                personality,
+            } => write!(
+                f,
+                "customer {customer:?} has undefined personality {personality:?}"
+            ),
+            LoadError::AwardCount { count } => {
+                write!(f, "at most 255 awards are supported, found {count}")
+            }
+            LoadError::ZeroRule { rule } => write!(f, "rule {rule} must be at least 1"),
+        }
+    }
+}
+impl std::error::Error for LoadError {}
+
+pub(crate) fn read_json<T: serde::Deserialize<'static>>(path: PathBuf) -> Result<T, LoadError> {
+    let text: &'static str = match fs::read_to_string(&path) {
+        Ok(text) => text.leak(),
+        Err(source) => return Err(LoadError::MissingFile { path, source }),
+    };
+    serde_json::from_str(text).map_err(|source| LoadError::Json { path, source })
+}
+
+impl GameRules {
+    pub fn load(path: &Path) -> Result<Self, Vec<LoadError>> {
+        let rules: Self = read_json(path.to_owned()).map_err(|err| vec![err])?;
+        let errors = rules.validate();
+        if errors.is_empty() {
+            Ok(rules)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn validate(&self) -> Vec<LoadError> {
+        [
+            (
+                "studentLoanIntervalMonths",
+                self.student_loan_interval_months,
+            ),
+            ("marksLimit", self.marks_limit),
+            ("maxAwardsInARow", self.max_awards_in_a_row),
+            ("repeatAwardLimit", self.repeat_award_limit),
+        ]
+        .into_iter()
+        .filter(|&(_, value)| value == 0)
+        .map(|(rule, _)| LoadError::ZeroRule { rule })
+        .collect()
+    }
 }
 
 impl InputData {
-    pub fn load(map_name: &str) -> Self {
-        let awards: &'static str = fs::read_to_string(format!("data/Awards-{map_name}.json"))
-            .unwrap()
-            .leak();
-        let map: &'static str = fs::read_to_string(format!("data/Map-{map_name}.json"))
-            .unwrap()
-            .leak();
-        let personalities: &'static str =
-            fs::read_to_string(format!("data/Personalities-{map_name}.json"))
-                .unwrap()
-                .leak();
-        let mut awards: Vec<(_, _)> = serde_json::from_str::<model::Awards>(awards)
-            .unwrap()
-            .awards
-            .into_iter()
-            .collect();
+    /// Loads the map with the default `GameRules`
+    pub fn load(data_dir: &Path, map_name: &str) -> Result<Self, Vec<LoadError>> {
+        Self::load_with_rules(data_dir, map_name, GameRules::default())
+    }
+
+    pub fn load_with_rules(
+        data_dir: &Path,
+        map_name: &str,
+        rules: GameRules,
+    ) -> Result<Self, Vec<LoadError>> {
+        let awards = read_json::<model::Awards>(data_dir.join(format!("Awards-{map_name}.json")));
+        let map = read_json::<model::Map>(data_dir.join(format!("Map-{map_name}.json")));
+        let personalities = read_json::<model::Personalities>(
+            data_dir.join(format!("Personalities-{map_name}.json")),
+        );
+        let (awards, mut map, personalities) = match (awards, map, personalities) {
+            (Ok(awards), Ok(map), Ok(personalities)) => (awards, map, personalities),
+            (awards, map, personalities) => {
+                return Err([awards.err(), map.err(), personalities.err()]
+                    .into_iter()
+                    .flatten()
+                    .collect())
+            }
+        };
+        let mut errors = Vec::new();
+        let mut seen_personalities = BTreeSet::new();
+
+        let mut awards: Vec<(_, _)> = awards.awards.into_iter().collect();
         awards.sort_by(|(_, a), (_, b)| f64::total_cmp(&a.base_happiness, &b.base_happiness));
-        Self {
+        for customer in &mut map.customers {
+            customer.personality = customer.personality.to_lowercase();
+        }
+        let ret = Self {
             awards: awards
                 .into_iter()
                 .enumerate()
                 .map(|(i, (k, mut v))| {
-                    v.id = std::num::NonZeroU8::new(i as u8 + 1).unwrap();
+                    v.id = std::num::NonZeroU8::new((i + 1).min(255) as u8).unwrap();
+                    v.interest_rebate = v
+                        .interest_rebate
+                        .or_else(|| rules.award_interest_rebates.get(&k).copied());
                     (&*k.leak(), v)
                 })
                 .collect(),
-            personalities: serde_json::from_str::<model::Personalities>(personalities)
-                .unwrap()
+            personalities: personalities
                 .personalities
                 .into_iter()
-                .map(|(k, mut v)| {
+                .filter_map(|(k, mut v)| {
                     let k = k.to_lowercase();
+                    if !seen_personalities.insert(k.clone()) {
+                        errors.push(LoadError::DuplicatePersonality { personality: k });
+                        return None;
+                    }
                     v.months_limit_multiplier = match &*k {
                         "conservative" => 1,
                         "risktaker" => 2,
                         "innovative" => 3,
                         "practical" => 4,
                         "spontaneous" => 5,
-                        _ => unreachable!(),
+                        _ => {
+                            errors.push(LoadError::UnknownPersonality { personality: k });
+                            return None;
+                        }
                     };
-                    (k, v)
+                    Some((k, v))
                 })
                 .collect(),
-            map: {
-                let mut map = serde_json::from_str::<model::Map>(&map).unwrap();
-                for customer in &mut map.customers {
-                    customer.personality = customer.personality.to_lowercase();
-                }
-                map
-            },
+            map,
+            rules,
+        };
+        errors.extend(ret.validate());
+        if errors.is_empty() {
+            Ok(ret)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn validate(&self) -> Vec<LoadError> {
+        let mut errors = self.rules.validate();
+        let mut seen = BTreeSet::new();
+        for customer in &self.map.customers {
+            if !seen.insert(customer.name) {
+                errors.push(LoadError::DuplicateCustomer {
+                    customer: customer.name,
+                });
+            }
+            if !self.personalities.contains_key(&customer.personality) {
+                errors.push(LoadError::UndefinedPersonality {
+                    customer: customer.name,
+                    personality: customer.personality.clone(),
+                });
+            }
         }
+        // Ids must fit a `NonZeroU8`
+        if self.awards.len() > u8::MAX as usize {
+            errors.push(LoadError::AwardCount {
+                count: self.awards.len(),
+            });
+        }
+        errors
+    }
+
+    /// Awards ordered by id, so that award `id` is at index `id - 1`
+    pub fn awards_by_id(&self) -> Vec<(&'static str, Award)> {
+        let mut ret: Vec<_> = self.awards.iter().map(|(&n, &a)| (n, a)).collect();
+        ret.sort_by_key(|(_, a)| a.id);
+        ret
     }
 }
 
 impl Request {
-    fn create_of_per_customer(
+    pub fn create_of_per_customer(
         indata: &InputData,
         submission: &[(&'static str, CustomerSubmission)],
     ) -> Self {
@@ -111,90 +281,324 @@ impl Request {
                 .collect(),
         }
     }
+    pub fn to_per_customer(&self) -> Vec<(&'static str, CustomerSubmission)> {
+        self.proposals
+            .iter()
+            .map(|proposal| {
+                (
+                    proposal.customer_name,
+                    CustomerSubmission {
+                        months_to_pay_back_loan: proposal.months_to_pay_back_loan,
+                        yearly_interest_rate: proposal.yearly_interest_rate,
+                        awards: self
+                            .iterations
+                            .iter()
+                            .map(|actions| match actions.get(proposal.customer_name) {
+                                Some(Action {
+                                    type_: "Award",
+                                    award,
+                                }) => Some(*award),
+                                _ => None,
+                            })
+                            .collect(),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    Transport(reqwest::Error),
+    /// 4xx, the body holds the validation message of the server
+    Validation {
+        status: StatusCode,
+        message: String,
+    },
+    /// 5xx
+    Server {
+        status: StatusCode,
+        body: String,
+    },
+    Decode {
+        source: serde_json::Error,
+        body: String,
+    },
+    /// Still answered 429 after `RetryPolicy::max_rate_limited` retries
+    RateLimited {
+        retries: usize,
+    },
+}
+impl ApiError {
+    fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Transport(_) | ApiError::Server { .. } => true,
+            ApiError::Validation { .. }
+            | ApiError::Decode { .. }
+            | ApiError::RateLimited { .. } => false,
+        }
+    }
+}
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport(source) => write!(f, "transport error: {source}"),
+            ApiError::Validation { status, message } => {
+                write!(f, "rejected by server ({status}): {message}")
+            }
+            ApiError::Server { status, body } => write!(f, "server error ({status}): {body}"),
+            ApiError::Decode { source, body } => {
+                write!(f, "undecodable response ({source}): {body}")
+            }
+            ApiError::RateLimited { retries } => {
+                write!(f, "still rate limited after {retries} retries")
+            }
+        }
+    }
+}
+impl std::error::Error for ApiError {}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Retries of transient failures per call, not counting 429s
+    pub max_retries: usize,
+    /// Retries of 429s per call, which wait for the rate limiter instead of backing off
+    pub max_rate_limited: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            max_rate_limited: 100,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// Sustained rate of one call per `interval`
+    pub interval: Duration,
+    /// Calls that may be made back-to-back after being idle
+    pub burst: u32,
+}
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            interval: Api::API_DELAY,
+            burst: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate_limit: RateLimit,
+    /// Negative when callers are queued waiting for tokens
+    tokens: f64,
+    last_refill: Instant,
+}
+impl TokenBucket {
+    fn new(rate_limit: RateLimit, now: Instant) -> Self {
+        Self {
+            rate_limit,
+            tokens: rate_limit.burst as f64,
+            last_refill: now,
+        }
+    }
+    /// Reserves a token at `now`, returning when it may be used
+    fn acquire(&mut self, now: Instant) -> Instant {
+        let refilled = (now - self.last_refill).as_secs_f64()
+            / self
+                .rate_limit
+                .interval
+                .as_secs_f64()
+                .max(f64::MIN_POSITIVE);
+        self.tokens = (self.tokens + refilled).min(self.rate_limit.burst as f64);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            now
+        } else {
+            now + self.rate_limit.interval.mul_f64(-self.tokens)
+        }
+    }
 }
 
 pub struct Api {
     api_key: &'static str,
-    earliest_next_call: Cell<Instant>,
-    num_calls: Cell<usize>,
+    endpoint: String,
+    rate_limiter: Mutex<TokenBucket>,
+    num_calls: AtomicUsize,
+    retry_policy: RetryPolicy,
+    cache: Option<EvaluationCache>,
+    recorder: Option<FixtureRecorder>,
     client: reqwest::Client,
 }
 impl Api {
-    const API_DELAY: Duration = Duration::from_millis(100);
-    const ENDPOINT: &str = "https://api.considition.com/game";
+    pub const API_DELAY: Duration = Duration::from_millis(100);
+    pub const ENDPOINT: &str = "https://api.considition.com/game";
 
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let api_key = fs::read_to_string(".api-key")
             .expect("API KEY in `./.api-key`")
             .leak()
             .trim();
+        Self::with_api_key(api_key)
+    }
+    pub fn with_api_key(api_key: &'static str) -> Self {
         tracing::info!(api_key);
         Self {
             api_key,
-            earliest_next_call: Cell::new(Instant::now()),
-            num_calls: Cell::new(0),
+            endpoint: Self::ENDPOINT.to_owned(),
+            rate_limiter: Mutex::new(TokenBucket::new(RateLimit::default(), Instant::now())),
+            num_calls: AtomicUsize::new(0),
+            retry_policy: RetryPolicy::default(),
+            cache: None,
+            recorder: None,
             client: reqwest::Client::new(),
         }
     }
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
+    pub fn with_rate_limit(self, rate_limit: RateLimit) -> Self {
+        Self {
+            rate_limiter: Mutex::new(TokenBucket::new(rate_limit, Instant::now())),
+            ..self
+        }
+    }
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+    pub fn with_cache(self, cache: EvaluationCache) -> Self {
+        Self {
+            cache: Some(cache),
+            ..self
+        }
+    }
+    pub fn with_recorder(self, recorder: FixtureRecorder) -> Self {
+        Self {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
     pub fn num_calls(&self) -> usize {
-        self.num_calls.get()
+        self.num_calls.load(Ordering::Relaxed)
     }
     fn acquire_slot(&self) -> Instant {
-        let old_slot = self.earliest_next_call.get();
-        let next_slot = (old_slot + Self::API_DELAY).max(Instant::now());
-        self.earliest_next_call.set(next_slot);
-        next_slot
-    }
-    async fn call(&self, request: &Request) -> Response {
-        tokio::time::sleep_until(self.acquire_slot()).await;
-
-        self.num_calls.set(self.num_calls.get() + 1);
-
-        let resp = loop {
-            let response = self
-                .client
-                .post(Self::ENDPOINT)
-                .json(request)
-                .header("x-api-key", self.api_key)
-                .send()
-                .await
-                .unwrap();
-            match response.status() {
-                StatusCode::TOO_MANY_REQUESTS => {
-                    eprint!("429");
-                    tokio::time::sleep_until(self.acquire_slot()).await;
-                    continue;
+        self.rate_limiter.lock().unwrap().acquire(Instant::now())
+    }
+    async fn call(&self, request: &Request) -> Result<Response, ApiError> {
+        self.num_calls.fetch_add(1, Ordering::Relaxed);
+
+        let mut retries = 0;
+        let mut rate_limited = 0;
+        let mut backoff = self.retry_policy.initial_backoff;
+        loop {
+            tokio::time::sleep_until(self.acquire_slot()).await;
+            let err = match self.try_call(request).await {
+                Ok(Some(resp)) => {
+                    tracing::trace!(?resp);
+                    return Ok(resp);
                 }
-                _ => {
-                    eprint!(".");
-                    if response.status().is_success() {
-                        break response.json().await.unwrap();
-                    } else {
-                        panic!(
-                            "{}\n{}\n{:#?}\n",
-                            response.status(),
-                            response.text().await.unwrap(),
-                            //request
-                            1
-                        );
+                Ok(None) => {
+                    eprint!("429");
+                    if rate_limited >= self.retry_policy.max_rate_limited {
+                        return Err(ApiError::RateLimited {
+                            retries: rate_limited,
+                        });
                     }
+                    rate_limited += 1;
+                    continue;
                 }
+                Err(err) => err,
+            };
+            if !err.is_transient() || retries >= self.retry_policy.max_retries {
+                return Err(err);
             }
-        };
-        tracing::trace!(?resp);
-        resp
+            retries += 1;
+            tracing::warn!(%err, retries, ?backoff, "retrying");
+            tokio::time::sleep(backoff).await;
+            backoff = backoff
+                .mul_f64(self.retry_policy.multiplier)
+                .min(self.retry_policy.max_backoff);
+        }
+    }
+    /// `Ok(None)` when rate limited
+    async fn try_call(&self, request: &Request) -> Result<Option<Response>, ApiError> {
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .json(request)
+            .header("x-api-key", self.api_key)
+            .send()
+            .await
+            .map_err(ApiError::Transport)?;
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Ok(None);
+        }
+        eprint!(".");
+        let body = response.text().await.map_err(ApiError::Transport)?;
+        if status.is_success() {
+            serde_json::from_str(&body)
+                .map(Some)
+                .map_err(|source| ApiError::Decode { source, body })
+        } else if status.is_client_error() {
+            Err(ApiError::Validation {
+                status,
+                message: body,
+            })
+        } else {
+            Err(ApiError::Server { status, body })
+        }
     }
     pub async fn evaluate(
         &self,
         indata: &InputData,
         submission: &[(&'static str, CustomerSubmission)],
-    ) -> crate::model::Score {
+    ) -> Result<Score, ApiError> {
         let request = Request::create_of_per_customer(indata, submission);
-        let response = self.call(&request).await;
-        response.score
+        let cached = self.cache.as_ref().and_then(|cache| cache.get(&request));
+        let (response, timestamp) = match cached {
+            Some(entry) => (entry.response, entry.timestamp),
+            None => {
+                let response = self.call(&request).await?;
+                if let Some(cache) = &self.cache {
+                    if let Err(err) = cache.insert(&request, &response) {
+                        tracing::warn!(%err, "failed caching evaluation");
+                    }
+                }
+                (response, crate::cache::unix_now())
+            }
+        };
+        // Cache hits too, with the time the server answered, as the cache is kept per endpoint
+        if let Some(recorder) = &self.recorder {
+            if let Err(err) = recorder.record(&self.endpoint, timestamp, &request, &response) {
+                tracing::warn!(%err, "failed recording fixture");
+            }
+        }
+        Ok(response.score)
     }
 }
 
+// Shared through `Arc` between tokio tasks and rayon threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Api>();
+};
+
 impl fmt::Display for Score {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let Score {
@@ -211,3 +615,177 @@ impl fmt::Display for Score {
         )
     }
 }
+
+#[test]
+fn test_load_errors() {
+    let data_dir = crate::test_util::data_dir();
+    let dir = std::env::temp_dir().join(format!("load-errors-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["Awards", "Map", "Personalities"] {
+        let file = format!("{file}-Gothenburg.json");
+        fs::copy(data_dir.join(&file), dir.join(&file)).unwrap();
+    }
+    let personalities = dir.join("Personalities-Gothenburg.json");
+    let text = fs::read_to_string(&personalities).unwrap();
+    fs::write(
+        &personalities,
+        text.replace("\"riskTaker\"", "\"Conservative\""),
+    )
+    .unwrap();
+    let errors = InputData::load(&dir, "Gothenburg").unwrap_err();
+    assert!(errors.iter().any(|err| matches!(
+        err,
+        LoadError::DuplicatePersonality { personality } if personality == "conservative"
+    )));
+
+    fs::write(dir.join("Awards-Gothenburg.json"), "{\n  \"Awards\": [").unwrap();
+    let errors = InputData::load(&dir, "Gothenburg").unwrap_err();
+    let message = errors[0].to_string();
+    assert_eq!(message.matches("line 2").count(), 1, "{message}");
+
+    let rules = dir.join("Rules.json");
+    let text = fs::read_to_string(data_dir.join("Rules-2024.json")).unwrap();
+    let text = text
+        .replace("\"marksLimit\": 3", "\"marksLimit\": 0")
+        .replace("\"repeatAwardLimit\": 3", "\"repeatAwardLimit\": 0");
+    fs::write(&rules, text).unwrap();
+    let errors = GameRules::load(&rules).unwrap_err();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(
+        &errors[..],
+        [
+            LoadError::ZeroRule { rule: "marksLimit" },
+            LoadError::ZeroRule {
+                rule: "repeatAwardLimit"
+            }
+        ]
+    ));
+}
+
+#[test]
+fn test_interest_rebate_precedence() {
+    let data_dir = crate::test_util::data_dir();
+    let dir = std::env::temp_dir().join(format!("rebates-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for file in ["Map", "Personalities"] {
+        let file = format!("{file}-Gothenburg.json");
+        fs::copy(data_dir.join(&file), dir.join(&file)).unwrap();
+    }
+    let awards = fs::read_to_string(data_dir.join("Awards-Gothenburg.json")).unwrap();
+    let awards = awards.replace(
+        "\"NoInterestRate\": {",
+        "\"NoInterestRate\": {\n      \"interestRebate\": 0.25,",
+    );
+    fs::write(dir.join("Awards-Gothenburg.json"), awards).unwrap();
+    let indata = InputData::load(&dir, "Gothenburg").unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The rules default to 1 for NoInterestRate and 0.5 for HalfInterestRate
+    assert_eq!(indata.awards["NoInterestRate"].interest_rebate, Some(0.25));
+    assert_eq!(indata.awards["HalfInterestRate"].interest_rebate, Some(0.5));
+    assert_eq!(indata.awards["IkeaCheck"].interest_rebate, None);
+}
+
+#[test]
+fn test_token_bucket() {
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+    let rate_limit = RateLimit {
+        interval: Duration::from_millis(100),
+        burst: 3,
+    };
+    let mut bucket = TokenBucket::new(rate_limit, start);
+    // The burst goes through at once, then callers queue one interval apart
+    let slots: Vec<_> = (0..5).map(|_| bucket.acquire(start)).collect();
+    assert_eq!(slots, [at(0), at(0), at(0), at(100), at(200)]);
+    // Idle time refills no more than the burst
+    let slots: Vec<_> = (0..4).map(|_| bucket.acquire(at(10_000))).collect();
+    assert_eq!(slots, [at(10_000), at(10_000), at(10_000), at(10_100)]);
+    // Callers keeping to the sustained rate are not delayed
+    for ms in (10_200..11_000).step_by(100) {
+        assert_eq!(bucket.acquire(at(ms)), at(ms));
+    }
+    // Nor do they save up tokens beyond the one they use
+    assert_eq!(bucket.acquire(at(11_000)), at(11_000));
+    assert_eq!(bucket.acquire(at(11_000)), at(11_100));
+}
+
+#[tokio::test]
+async fn test_retries() {
+    use crate::{mock::MockConfig, test_util, whitebox};
+
+    let indata = test_util::load("Gothenburg");
+    let submission = test_util::whole_game_submissions(&indata, test_util::min_rate, |_| None);
+    let expected = whitebox::simulate(&indata, &submission).unwrap();
+    let retry_policy = RetryPolicy {
+        max_retries: 2,
+        max_rate_limited: 3,
+        initial_backoff: Duration::from_millis(50),
+        max_backoff: Duration::from_secs(1),
+        multiplier: 2.0,
+    };
+    let api_with_faults = |faults: Vec<StatusCode>| async {
+        let endpoint = test_util::spawn_mock_server(MockConfig {
+            faults,
+            ..test_util::mock_config(GameRules::default())
+        })
+        .await;
+        test_util::mock_api(endpoint).with_retry_policy(retry_policy.clone())
+    };
+
+    // Backing off 50 and then 100 ms
+    let api = api_with_faults(vec![StatusCode::SERVICE_UNAVAILABLE; 2]).await;
+    let start = Instant::now();
+    assert_eq!(api.evaluate(&indata, &submission).await.unwrap(), expected);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(api.num_calls(), 1);
+
+    let api = api_with_faults(vec![StatusCode::SERVICE_UNAVAILABLE; 3]).await;
+    assert!(matches!(
+        api.evaluate(&indata, &submission).await,
+        Err(ApiError::Server {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            ..
+        })
+    ));
+
+    // Every call after the first is answered 429
+    let endpoint = test_util::spawn_mock_server(MockConfig {
+        min_interval: Duration::from_secs(3600),
+        ..test_util::mock_config(GameRules::default())
+    })
+    .await;
+    let api = test_util::mock_api(endpoint).with_retry_policy(retry_policy.clone());
+    api.evaluate(&indata, &submission).await.unwrap();
+    assert!(matches!(
+        api.evaluate(&indata, &submission).await,
+        Err(ApiError::RateLimited { retries: 3 })
+    ));
+}
+
+#[tokio::test]
+async fn test_error_classification() {
+    use crate::{mock::MockConfig, test_util};
+
+    let indata = test_util::load("Gothenburg");
+    let submission = test_util::whole_game_submissions(&indata, test_util::min_rate, |_| None);
+    // Neither fault is retried, as a retry would succeed
+    let endpoint = test_util::spawn_mock_server(MockConfig {
+        faults: vec![StatusCode::OK, StatusCode::BAD_REQUEST],
+        ..test_util::mock_config(GameRules::default())
+    })
+    .await;
+    let api = test_util::mock_api(endpoint);
+    assert!(matches!(
+        api.evaluate(&indata, &submission).await,
+        Err(ApiError::Decode { .. })
+    ));
+    assert!(matches!(
+        api.evaluate(&indata, &submission).await,
+        Err(ApiError::Validation {
+            status: StatusCode::BAD_REQUEST,
+            ..
+        })
+    ));
+    api.evaluate(&indata, &submission).await.unwrap();
+}
@@ -0,0 +1,360 @@
+use crate::{
+    api::{Api, ApiError, CustomerSubmission, InputData},
+    model::Score,
+    whitebox::{self, SimulationError},
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use serde::Serialize;
+use std::fmt;
+
+type Submission = Vec<(&'static str, CustomerSubmission)>;
+
+/// Whether the whitebox disagrees with the server beyond rounding
+pub fn diverges(real: &Score, whitebox: &Score) -> bool {
+    real.environmental_impact != whitebox.environmental_impact
+        || (real.happiness_score - whitebox.happiness_score).abs() > 0.4
+        || (real.total_score - whitebox.total_score).abs() > 1e-5
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ScoreDelta {
+    pub environmental_impact: f64,
+    pub happiness_score: f64,
+    pub total_profit: f64,
+    pub total_score: f64,
+}
+impl ScoreDelta {
+    /// Server minus whitebox
+    pub fn of(real: &Score, whitebox: &Score) -> Self {
+        Self {
+            environmental_impact: real.environmental_impact - whitebox.environmental_impact,
+            happiness_score: real.happiness_score - whitebox.happiness_score,
+            total_profit: real.total_profit - whitebox.total_profit,
+            total_score: real.total_score - whitebox.total_score,
+        }
+    }
+}
+
+/// Simulation phase that most likely diverges, guessed from the shape of the delta
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Phase {
+    /// Environmental impact differs, so the server did not accept the same customers
+    Acceptance,
+    /// Only reproduces with several customers, e.g. the bank running out of budget
+    Budget,
+    /// Happiness off by missed payment marks, so capital after payday and bills differs
+    PaydayOrBills,
+    /// Profit off without awards involved, so interest or amortization differs
+    Loan,
+    /// Disappears when awards from `month` on are dropped
+    Award,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Discrepancy {
+    pub real: Score,
+    /// None if the whitebox rejected the submission, see `whitebox_error`
+    pub whitebox: Option<Score>,
+    pub whitebox_error: Option<String>,
+    pub delta: Option<ScoreDelta>,
+    /// Single customer whose solo submission reproduces a divergence
+    pub customer: Option<&'static str>,
+    /// Earliest month whose award is needed to reproduce the divergence for `customer`
+    pub month: Option<usize>,
+    pub phase: Phase,
+}
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(ScoreDelta {
+            environmental_impact,
+            happiness_score,
+            total_profit,
+            total_score,
+        }) = self.delta
+        else {
+            return write!(
+                f,
+                "{:?} whitebox: {}",
+                self.phase,
+                self.whitebox_error.as_deref().unwrap_or_default()
+            );
+        };
+        write!(
+            f,
+            "{:?} customer={:?} month={:?} Δenv={environmental_impact:.2} Δhap={happiness_score:.2} Δpro={total_profit:.2} Δtot={total_score:.2}",
+            self.phase, self.customer, self.month,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditReport {
+    pub map_name: &'static str,
+    pub num_submissions: usize,
+    /// Submissions rejected by the server, e.g. for exhausting the budget
+    pub num_rejected: usize,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+pub fn random_submission(
+    indata: &InputData,
+    num_customers: usize,
+    rng: &mut impl Rng,
+) -> Submission {
+    let award_names: Vec<&'static str> = indata.awards.keys().copied().collect();
+    let award_probability = rng.gen_range(0.0..1.0);
+    indata
+        .map
+        .customers
+        .choose_multiple(rng, num_customers)
+        .map(|customer| {
+            let personality = &indata.personalities[&customer.personality];
+            let months_limit =
+                personality.months_limit_multiplier * indata.map.game_length_in_months;
+            (
+                customer.name,
+                CustomerSubmission {
+                    months_to_pay_back_loan: rng.gen_range(1..=months_limit),
+                    yearly_interest_rate: rng.gen_range(
+                        personality.accepted_min_interest..=personality.accepted_max_interest,
+                    ),
+                    awards: (0..indata.map.game_length_in_months)
+                        .map(|_| {
+                            rng.gen_bool(award_probability)
+                                .then(|| *award_names.choose(rng).unwrap())
+                        })
+                        .collect(),
+                },
+            )
+        })
+        .collect()
+}
+
+// Evaluates randomized submissions with both the API and the whitebox, and shrinks every
+// divergent one to a single customer and month to guess which simulation phase differs.
+pub async fn audit(
+    api: &Api,
+    indata: &InputData,
+    num_submissions: usize,
+    customers_per_submission: usize,
+    seed: u64,
+) -> Result<AuditReport, ApiError> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let submissions: Vec<Submission> = (0..num_submissions)
+        .map(|_| random_submission(indata, customers_per_submission, &mut rng))
+        .collect();
+    let reals =
+        futures::future::join_all(submissions.iter().map(|s| api.evaluate(indata, s))).await;
+
+    let mut discrepancies = Vec::new();
+    let mut num_rejected = 0;
+    for (submission, real) in submissions.iter().zip(reals) {
+        let real = match real {
+            Ok(real) => real,
+            Err(ApiError::Validation { message, .. }) => {
+                tracing::warn!(message, "rejected");
+                num_rejected += 1;
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let whitebox = whitebox::simulate(indata, submission);
+        if whitebox
+            .as_ref()
+            .is_ok_and(|whitebox| !diverges(&real, whitebox))
+        {
+            continue;
+        }
+        let discrepancy = shrink(api, indata, submission, real, whitebox).await?;
+        tracing::info!(%discrepancy);
+        discrepancies.push(discrepancy);
+    }
+    Ok(AuditReport {
+        map_name: indata.map.name,
+        num_submissions,
+        num_rejected,
+        discrepancies,
+    })
+}
+
+async fn shrink(
+    api: &Api,
+    indata: &InputData,
+    submission: &Submission,
+    real: Score,
+    whitebox: Result<Score, SimulationError>,
+) -> Result<Discrepancy, ApiError> {
+    let whitebox = match whitebox {
+        Ok(whitebox) => whitebox,
+        Err(err) => {
+            return Ok(Discrepancy {
+                real,
+                whitebox: None,
+                whitebox_error: Some(err.to_string()),
+                delta: None,
+                customer: None,
+                month: None,
+                phase: match err {
+                    SimulationError::BudgetExhausted { .. } => Phase::Budget,
+                    _ => Phase::Acceptance,
+                },
+            })
+        }
+    };
+    let delta = ScoreDelta::of(&real, &whitebox);
+    let mut ret = Discrepancy {
+        real,
+        whitebox: Some(whitebox),
+        whitebox_error: None,
+        delta: Some(delta),
+        customer: None,
+        month: None,
+        phase: Phase::Unknown,
+    };
+    if delta.environmental_impact != 0.0 {
+        ret.phase = Phase::Acceptance;
+    }
+
+    let mut solo = None;
+    for entry in submission {
+        let candidate = vec![entry.clone()];
+        if diverges_for(api, indata, &candidate).await? {
+            solo = Some(candidate);
+            break;
+        }
+    }
+    let Some(solo) = solo else {
+        if ret.phase == Phase::Unknown {
+            ret.phase = Phase::Budget;
+        }
+        return Ok(ret);
+    };
+    ret.customer = Some(solo[0].0);
+
+    // Smallest `k` such that keeping only the awards of months `..k` still diverges
+    let with_awards_before = |k: usize| {
+        let mut candidate = solo.clone();
+        for award in &mut candidate[0].1.awards[k..] {
+            *award = None;
+        }
+        candidate
+    };
+    let (mut lo, mut hi) = (0, indata.map.game_length_in_months);
+    if diverges_for(api, indata, &with_awards_before(lo)).await? {
+        hi = lo;
+    }
+    while lo + 1 < hi {
+        let mid = (lo + hi) / 2;
+        if diverges_for(api, indata, &with_awards_before(mid)).await? {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    if hi > 0 {
+        ret.month = Some(hi - 1);
+    }
+
+    if ret.phase == Phase::Unknown {
+        ret.phase = if ret.month.is_some() {
+            Phase::Award
+        } else if delta.total_profit.abs() < 1.0
+            && delta.happiness_score != 0.0
+            && (delta.happiness_score / indata.rules.mark_happiness_penalty)
+                .fract()
+                .abs()
+                < 1e-6
+        {
+            Phase::PaydayOrBills
+        } else if delta.total_profit.abs() >= 1.0 {
+            Phase::Loan
+        } else {
+            Phase::Unknown
+        };
+    }
+    Ok(ret)
+}
+
+async fn diverges_for(
+    api: &Api,
+    indata: &InputData,
+    submission: &Submission,
+) -> Result<bool, ApiError> {
+    let whitebox = whitebox::simulate(indata, submission);
+    match (api.evaluate(indata, submission).await, whitebox) {
+        (Ok(real), Ok(whitebox)) => Ok(diverges(&real, &whitebox)),
+        (Ok(_), Err(_)) => Ok(true),
+        (Err(ApiError::Validation { .. }), whitebox) => Ok(whitebox.is_ok()),
+        (Err(err), _) => Err(err),
+    }
+}
+
+#[tokio::test]
+async fn test_audit_against_changed_rules() {
+    use crate::{model::GameRules, test_util};
+
+    // The server pays for half the interest in months with an IkeaCheck, the whitebox does not
+    let mut rules = GameRules::default();
+    rules
+        .award_interest_rebates
+        .insert("IkeaCheck".to_owned(), 0.5);
+    let server_indata =
+        InputData::load_with_rules(&test_util::data_dir(), "Gothenburg", rules.clone()).unwrap();
+    let endpoint = test_util::spawn_mock_server(test_util::mock_config(rules)).await;
+    let api = test_util::mock_api(endpoint);
+    let indata = test_util::load("Gothenburg");
+    let report = audit(&api, &indata, 20, 2, 0).await.unwrap();
+
+    let diverges_locally =
+        |submission: &Submission| match whitebox::simulate(&server_indata, submission) {
+            Ok(real) => whitebox::simulate(&indata, submission)
+                .map_or(true, |whitebox| diverges(&real, &whitebox)),
+            Err(_) => false,
+        };
+    // The same submissions as the audit
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let divergent: Vec<Submission> = (0..20)
+        .map(|_| random_submission(&indata, 2, &mut rng))
+        .filter(|submission| diverges_locally(submission))
+        .collect();
+    assert!(!divergent.is_empty());
+    assert_eq!(report.discrepancies.len(), divergent.len());
+    for (discrepancy, submission) in report.discrepancies.iter().zip(&divergent) {
+        let (customer, solo) = submission
+            .iter()
+            .find(|entry| diverges_locally(&vec![(*entry).clone()]))
+            .unwrap();
+        let first_ikea_check = solo.awards.iter().position(|&a| a == Some("IkeaCheck"));
+        assert_eq!(discrepancy.customer, Some(*customer), "{discrepancy}");
+        assert_eq!(discrepancy.month, first_ikea_check, "{discrepancy}");
+        assert_eq!(discrepancy.phase, Phase::Award, "{discrepancy}");
+    }
+}
+
+#[tokio::test]
+async fn test_audit_classifies_missed_payments() {
+    use crate::{model::GameRules, test_util};
+
+    // Every mark costs 30 more happiness on the server than in the whitebox
+    let rules = GameRules {
+        mark_happiness_penalty: 30.0,
+        ..GameRules::default()
+    };
+    let indata =
+        InputData::load_with_rules(&test_util::data_dir(), "Gothenburg", rules.clone()).unwrap();
+    let endpoint = test_util::spawn_mock_server(test_util::mock_config(GameRules {
+        mark_happiness_penalty: 60.0,
+        ..rules
+    }))
+    .await;
+    let report = audit(&test_util::mock_api(endpoint), &indata, 20, 2, 0)
+        .await
+        .unwrap();
+    assert!(!report.discrepancies.is_empty());
+    for discrepancy in &report.discrepancies {
+        assert!(discrepancy.customer.is_some(), "{discrepancy}");
+        assert_eq!(discrepancy.month, None, "{discrepancy}");
+        assert_eq!(discrepancy.phase, Phase::PaydayOrBills, "{discrepancy}");
+    }
+}
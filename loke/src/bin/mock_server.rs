@@ -0,0 +1,50 @@
+use clap::Parser;
+use considition2024::{
+    mock::{self, MockConfig},
+    model::GameRules,
+};
+use std::{path::PathBuf, time::Duration};
+
+#[derive(Parser)]
+struct Cli {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+    /// Directory containing the `Awards-`, `Map-` and `Personalities-` files
+    #[arg(long, default_value = "data")]
+    data_dir: PathBuf,
+    /// Game rules file, otherwise the defaults
+    #[arg(long)]
+    rules: Option<PathBuf>,
+    /// Respond 429 to calls arriving sooner than this after the previous accepted call
+    #[arg(long, default_value_t = 0)]
+    min_interval_ms: u64,
+    /// Delay before responding to an accepted call
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+    let listener = tokio::net::TcpListener::bind(&cli.listen)
+        .await
+        .expect("binding listen address");
+    tracing::info!(endpoint = %format!("http://{}/game", listener.local_addr().unwrap()));
+    mock::serve(
+        listener,
+        MockConfig {
+            data_dir: cli.data_dir,
+            rules: cli
+                .rules
+                .map(|path| GameRules::load(&path).expect("loading rules"))
+                .unwrap_or_default(),
+            min_interval: Duration::from_millis(cli.min_interval_ms),
+            latency: Duration::from_millis(cli.latency_ms),
+            faults: Vec::new(),
+        },
+    )
+    .await
+    .expect("serving");
+}
@@ -0,0 +1,136 @@
+use crate::model::{Request, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct CachedEvaluation {
+    /// SHA-256 of the serialized request
+    pub key: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub response: Response,
+}
+
+/// Append-only JSONL file of API responses, keyed by the request that produced them, with one
+/// file per endpoint so that mock scores are never served in place of the server's
+pub struct EvaluationCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CachedEvaluation>>,
+}
+
+impl EvaluationCache {
+    const FILE_NAME: &str = "evaluations.jsonl";
+
+    fn path(cache_dir: &Path, endpoint: &str) -> PathBuf {
+        let endpoint_hash = format!("{:x}", Sha256::digest(endpoint.as_bytes()));
+        cache_dir.join(&endpoint_hash[..16]).join(Self::FILE_NAME)
+    }
+
+    pub fn open(cache_dir: &Path, endpoint: &str) -> io::Result<Self> {
+        let path = Self::path(cache_dir, endpoint);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut entries = HashMap::new();
+        match fs::File::open(&path) {
+            Ok(file) => {
+                for (i, line) in io::BufReader::new(file).lines().enumerate() {
+                    match serde_json::from_str::<CachedEvaluation>(&line?) {
+                        Ok(entry) => {
+                            entries.insert(entry.key.clone(), entry);
+                        }
+                        Err(err) => {
+                            tracing::warn!(path = %path.display(), line = i + 1, %err, "ignoring")
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        tracing::info!(path = %path.display(), endpoint, entries = entries.len(), "opened cache");
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn clear(cache_dir: &Path, endpoint: &str) -> io::Result<()> {
+        match fs::remove_file(Self::path(cache_dir, endpoint)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            ret => ret,
+        }
+    }
+
+    pub fn key(request: &Request) -> String {
+        let serialized = serde_json::to_string(request).unwrap();
+        format!("{:x}", Sha256::digest(serialized.as_bytes()))
+    }
+
+    pub fn get(&self, request: &Request) -> Option<CachedEvaluation> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&Self::key(request)).cloned()
+    }
+
+    pub fn insert(&self, request: &Request, response: &Response) -> io::Result<()> {
+        let entry = CachedEvaluation {
+            key: Self::key(request),
+            timestamp: unix_now(),
+            response: response.clone(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry).unwrap())?;
+        entries.insert(entry.key.clone(), entry);
+        Ok(())
+    }
+}
+
+/// Seconds since the unix epoch
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[test]
+fn test_endpoints_do_not_share_entries() {
+    use crate::model::Score;
+
+    let cache_dir = std::env::temp_dir().join(format!("cache-{}", std::process::id()));
+    let request = Request {
+        map_name: "Gothenburg",
+        proposals: vec![],
+        iterations: vec![],
+    };
+    let response = Response {
+        game_id: "mock-0".to_owned(),
+        message: (),
+        score: Score {
+            map_name: "Gothenburg".to_owned(),
+            environmental_impact: 0.0,
+            happiness_score: 0.0,
+            total_profit: 0.0,
+            total_score: 0.0,
+        },
+    };
+    let mock = EvaluationCache::open(&cache_dir, "http://127.0.0.1:8080/game").unwrap();
+    mock.insert(&request, &response).unwrap();
+    let real = EvaluationCache::open(&cache_dir, "https://api.considition.com/game").unwrap();
+    let reopened = EvaluationCache::open(&cache_dir, "http://127.0.0.1:8080/game").unwrap();
+    fs::remove_dir_all(&cache_dir).unwrap();
+    assert!(real.get(&request).is_none());
+    assert!(reopened.get(&request).is_some());
+}
@@ -0,0 +1,199 @@
+use crate::{
+    cache::EvaluationCache,
+    model::{Request, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A request evaluated by the game server together with its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    /// Server that evaluated the request, only `Api::ENDPOINT` is the real game
+    pub endpoint: String,
+    pub map_name: &'static str,
+    /// Seconds since the unix epoch when the server answered
+    pub timestamp: u64,
+    pub request: Request,
+    pub response: Response,
+}
+
+/// Appends every evaluated request/response pair to a JSONL file of fixtures, once per endpoint
+pub struct FixtureRecorder {
+    path: PathBuf,
+    /// Endpoint and `EvaluationCache::key` of the fixtures in the file
+    recorded: Mutex<HashSet<(String, String)>>,
+}
+
+impl FixtureRecorder {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let recorded = match load(path) {
+            Ok(fixtures) => fixtures
+                .iter()
+                .map(|fixture| {
+                    (
+                        fixture.endpoint.clone(),
+                        EvaluationCache::key(&fixture.request),
+                    )
+                })
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err),
+        };
+        tracing::info!(path = %path.display(), recorded = recorded.len(), "recording fixtures");
+        Ok(Self {
+            path: path.to_owned(),
+            recorded: Mutex::new(recorded),
+        })
+    }
+
+    /// Appends the response unless the file already has one from `endpoint` to the same request,
+    /// `timestamp` being when the server answered
+    pub fn record(
+        &self,
+        endpoint: &str,
+        timestamp: u64,
+        request: &Request,
+        response: &Response,
+    ) -> io::Result<()> {
+        let key = (endpoint.to_owned(), EvaluationCache::key(request));
+        let mut recorded = self.recorded.lock().unwrap();
+        if recorded.contains(&key) {
+            return Ok(());
+        }
+        let fixture = Fixture {
+            endpoint: endpoint.to_owned(),
+            map_name: request.map_name,
+            timestamp,
+            request: request.clone(),
+            response: response.clone(),
+        };
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&fixture).unwrap())?;
+        recorded.insert(key);
+        Ok(())
+    }
+}
+
+/// All fixtures of a JSONL file, failing on lines that do not parse
+pub fn load(path: &Path) -> io::Result<Vec<Fixture>> {
+    let file = fs::File::open(path)?;
+    io::BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(line?.leak()).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {err}", path.display(), i + 1),
+                )
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_record_and_load() {
+    use crate::{api::Api, test_util, whitebox};
+
+    let indata = test_util::load("Gothenburg");
+    let customer = &indata.map.customers[0];
+    let submission = [test_util::whole_game_submission(
+        &indata,
+        customer,
+        test_util::min_rate,
+        |_| None,
+    )];
+    let request = Request::create_of_per_customer(&indata, &submission);
+    let response = Response {
+        game_id: "test".to_owned(),
+        message: (),
+        score: whitebox::simulate(&indata, &submission).unwrap(),
+    };
+
+    let path = std::env::temp_dir()
+        .join(format!("fixtures-{}", std::process::id()))
+        .join("Gothenburg.jsonl");
+    let _ = fs::remove_file(&path);
+    let recorder = FixtureRecorder::open(&path).unwrap();
+    recorder
+        .record(Api::ENDPOINT, 1, &request, &response)
+        .unwrap();
+    recorder
+        .record("http://localhost/game", 2, &request, &response)
+        .unwrap();
+    recorder
+        .record(Api::ENDPOINT, 3, &request, &response)
+        .unwrap();
+    // A rerun records nothing the file already has
+    let reopened = FixtureRecorder::open(&path).unwrap();
+    reopened
+        .record(Api::ENDPOINT, 4, &request, &response)
+        .unwrap();
+    let fixtures = load(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(fixtures.len(), 2);
+    assert_eq!(fixtures[0].endpoint, Api::ENDPOINT);
+    assert_eq!(fixtures[0].timestamp, 1);
+    assert_eq!(fixtures[1].endpoint, "http://localhost/game");
+    assert_eq!(fixtures[0].map_name, "Gothenburg");
+    assert_eq!(fixtures[1].response.score, response.score);
+    assert_eq!(
+        fixtures[0].request.to_per_customer()[0].1.awards,
+        submission[0].1.awards
+    );
+}
+
+#[tokio::test]
+async fn test_api_records_cache_hits_once() {
+    use crate::{model::GameRules, test_util};
+
+    let indata = test_util::load("Gothenburg");
+    let submission = test_util::whole_game_submissions(&indata, test_util::min_rate, |_| None);
+    let endpoint = test_util::spawn_mock_server(test_util::mock_config(GameRules::default())).await;
+    let dir = std::env::temp_dir().join(format!("recorded-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let api = |fixtures: &str| {
+        test_util::mock_api(endpoint.clone())
+            .with_cache(EvaluationCache::open(&dir, &endpoint).unwrap())
+            .with_recorder(FixtureRecorder::open(&dir.join(fixtures)).unwrap())
+    };
+    let fresh = api("fresh.jsonl");
+    fresh.evaluate(&indata, &submission).await.unwrap();
+    fresh.evaluate(&indata, &submission).await.unwrap();
+    // Reruns, answered from the cache
+    let rerun = api("fresh.jsonl");
+    rerun.evaluate(&indata, &submission).await.unwrap();
+    let cached = api("cached.jsonl");
+    cached.evaluate(&indata, &submission).await.unwrap();
+    let calls = fresh.num_calls() + rerun.num_calls() + cached.num_calls();
+    let fresh = load(&dir.join("fresh.jsonl")).unwrap();
+    let cached = load(&dir.join("cached.jsonl")).unwrap();
+    let request = Request::create_of_per_customer(&indata, &submission);
+    let entry = EvaluationCache::open(&dir, &endpoint)
+        .unwrap()
+        .get(&request)
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(calls, 1);
+    assert_eq!(fresh.len(), 1);
+    assert_eq!(cached.len(), 1);
+    assert_eq!(cached[0].endpoint, endpoint);
+    assert_eq!(cached[0].timestamp, entry.timestamp);
+    assert_eq!(cached[0].response.score, entry.response.score);
+}
@@ -0,0 +1,12 @@
+pub mod api;
+pub mod audit;
+pub mod cache;
+pub mod fixture;
+pub mod mock;
+pub mod model;
+pub mod opt;
+pub mod remoteopt;
+pub mod solver;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod whitebox;
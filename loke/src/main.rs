@@ -1,20 +1,207 @@
-mod api;
-mod model;
-mod opt;
-mod whitebox;
-
-use api::{Api, CustomerSubmission, InputData};
+use clap::Parser;
+use considition2024::{
+    api::{Api, CustomerSubmission, InputData, RateLimit, RetryPolicy},
+    audit,
+    cache::EvaluationCache,
+    fixture::FixtureRecorder,
+    model::{GameRules, Request, Score},
+    opt::{self, OptConfig, Preset},
+    remoteopt,
+    solver::SolverKind,
+    whitebox::{self, MonthTrace, SimulationError},
+};
 use itertools::Itertools;
-use model::Score;
-use std::iter;
+use rayon::prelude::*;
+use std::{fs, iter, path::PathBuf, time::Duration};
 use tokio::time::Instant;
 
+#[derive(Parser)]
+struct Cli {
+    /// Map to play, e.g. `Almhult`, `Gothenburg` or `Nottingham`
+    #[arg(long, global = true, default_value = "Almhult")]
+    map: String,
+    /// Directory containing the `Awards-`, `Map-` and `Personalities-` files
+    #[arg(long, global = true, default_value = "data")]
+    data_dir: PathBuf,
+    /// Override the budget of the map
+    #[arg(long, global = true)]
+    budget: Option<f64>,
+    /// Game rules file, e.g. `data/Rules-2024.json`, otherwise the defaults
+    #[arg(long, global = true)]
+    rules: Option<PathBuf>,
+    /// Game endpoint, e.g. of a local `mock_server`
+    #[arg(long, global = true, default_value = Api::ENDPOINT)]
+    endpoint: String,
+    /// API key, read from `./.api-key` if not given
+    #[arg(long, global = true)]
+    api_key: Option<String>,
+    /// Sustained API rate limit, one call per this many milliseconds
+    #[arg(long, global = true, default_value_t = Api::API_DELAY.as_millis() as u64)]
+    rate_limit_ms: u64,
+    /// API calls that may be made back-to-back after being idle
+    #[arg(long, global = true, default_value_t = RateLimit::default().burst)]
+    burst: u32,
+    /// Retries of transient API failures per call
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: usize,
+    /// Retries of 429 responses per call
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().max_rate_limited)]
+    max_rate_limited: usize,
+    /// Backoff before the first retry, doubled for every subsequent retry
+    #[arg(long, global = true, default_value_t = RetryPolicy::default().initial_backoff.as_millis() as u64)]
+    initial_backoff_ms: u64,
+    /// Directory of the on-disk cache of API evaluations, kept separately for every endpoint
+    #[arg(long, global = true, default_value = ".cache")]
+    cache_dir: PathBuf,
+    /// Neither read from nor write to the evaluation cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Discard all cached evaluations of the endpoint before running
+    #[arg(long, global = true)]
+    clear_cache: bool,
+    /// Append every request evaluated by the server, with its response, to this JSONL file unless
+    /// already recorded from the same endpoint
+    #[arg(long, global = true)]
+    record_fixtures: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Sweep interest rates against the real API
+    Sweep {
+        /// Only include this customer
+        #[arg(long)]
+        customer: Option<String>,
+    },
+    /// Optimize a submission locally using the whitebox
+    Optimize {
+        #[command(flatten)]
+        opt: OptArgs,
+        /// Write the submission as an API request to this file
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Also evaluate the submission with the real API
+        #[arg(long)]
+        submit: bool,
+    },
+    /// Refine a submission using real API scores as the objective
+    RemoteOptimize {
+        /// Settings of the local optimization when not starting from `--input`
+        #[command(flatten)]
+        opt: OptArgs,
+        /// Submission to start from, otherwise optimize locally first
+        #[arg(long, short)]
+        input: Option<PathBuf>,
+        /// Maximum number of API calls to spend
+        #[arg(long, default_value_t = 500)]
+        max_calls: usize,
+        /// Write the refined submission as an API request to this file
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Score a submission file using the whitebox
+    Simulate {
+        input: PathBuf,
+        /// Write the per-customer, per-month state to this file, as CSV if it ends in `.csv`
+        /// and as JSON otherwise
+        #[arg(long)]
+        trace: Option<PathBuf>,
+    },
+    /// Score a submission file using the real API
+    Submit { input: PathBuf },
+    /// Score a submission file using both the whitebox and the real API
+    Compare { input: PathBuf },
+    /// Look for divergences between the whitebox and the real API on random submissions
+    Audit {
+        #[arg(long, default_value_t = 20)]
+        submissions: usize,
+        #[arg(long, default_value_t = 3)]
+        customers_per_submission: usize,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Write the report as JSON to this file
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Time the exact award DP for every customer of the map, at the minimum rate and a
+    /// spread of months
+    BenchDp {
+        /// Only the first this many customers
+        #[arg(long)]
+        customers: Option<usize>,
+    },
+}
+
+#[derive(clap::Args)]
+struct OptArgs {
+    /// Optimizer settings to start from
+    #[arg(long, value_enum, default_value_t = Preset::Default)]
+    preset: Preset,
+    /// JSON file of optimizer settings, replacing the preset
+    #[arg(long)]
+    opt_config: Option<PathBuf>,
+    /// Override the number of particles per customer
+    #[arg(long)]
+    particles: Option<usize>,
+    /// Override the number of swarm iterations per customer
+    #[arg(long)]
+    iters: Option<u64>,
+    /// Override the per-customer (rate, months) optimizer
+    #[arg(long, value_enum)]
+    solver: Option<SolverKind>,
+    /// Seed of the randomized solvers, making runs on the same inputs reproducible
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+impl OptArgs {
+    fn config(&self) -> OptConfig {
+        let mut config = match &self.opt_config {
+            Some(path) => OptConfig::load(path).unwrap_or_else(|err| {
+                eprintln!("{err}");
+                std::process::exit(1)
+            }),
+            None => OptConfig::preset(self.preset),
+        };
+        if let Some(particles) = self.particles {
+            config.num_particles = particles;
+        }
+        if let Some(iters) = self.iters {
+            config.max_iters = iters;
+        }
+        if let Some(solver) = self.solver {
+            config.solver = solver;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        config
+    }
+}
+
+impl Command {
+    fn uses_api(&self) -> bool {
+        match self {
+            Command::Optimize { submit, .. } => *submit,
+            Command::Simulate { .. } | Command::BenchDp { .. } => false,
+            Command::Sweep { .. }
+            | Command::RemoteOptimize { .. }
+            | Command::Submit { .. }
+            | Command::Compare { .. }
+            | Command::Audit { .. } => true,
+        }
+    }
+}
+
 fn main() {
     use tracing_subscriber::Layer;
     tracing::subscriber::set_global_default(
         tracing_subscriber::filter::targets::Targets::new()
             .with_target("hyper_util", tracing::Level::INFO)
             .with_target("considition2024::api", tracing::Level::INFO)
+            .with_target("considition2024::opt", tracing::Level::INFO)
             .with_default(tracing::Level::TRACE)
             .with_subscriber(
                 tracing_subscriber::FmtSubscriber::builder()
@@ -24,26 +211,81 @@ fn main() {
     )
     .expect("enabling global logger");
 
-    //let indata = InputData::load("Gothenburg");
-    //let indata = InputData::load("Nottingham");
-    let mut indata = InputData::load("Almhult");
-    indata.map.budget -= 100_000.0; // TODO
+    let cli = Cli::parse();
+    let rules = match &cli.rules {
+        Some(rules) => GameRules::load(rules).unwrap_or_else(|errors| {
+            for error in errors {
+                eprintln!("{error}");
+            }
+            std::process::exit(1)
+        }),
+        None => GameRules::default(),
+    };
+    let mut indata =
+        InputData::load_with_rules(&cli.data_dir, &cli.map, rules).unwrap_or_else(|errors| {
+            for error in errors {
+                eprintln!("{error}");
+            }
+            std::process::exit(1)
+        });
+    if let Some(budget) = cli.budget {
+        indata.map.budget = budget;
+    }
     dbg!(&indata.awards);
 
-    let api = Api::new();
+    if cli.clear_cache {
+        EvaluationCache::clear(&cli.cache_dir, &cli.endpoint).expect("clearing cache");
+    }
+    let api = cli.command.uses_api().then(|| {
+        let api = match cli.api_key {
+            Some(api_key) => Api::with_api_key(api_key.leak()),
+            None => Api::new(),
+        };
+        let api = api
+            .with_endpoint(cli.endpoint.clone())
+            .with_rate_limit(RateLimit {
+                interval: Duration::from_millis(cli.rate_limit_ms),
+                burst: cli.burst,
+            })
+            .with_retry_policy(RetryPolicy {
+                max_retries: cli.max_retries,
+                max_rate_limited: cli.max_rate_limited,
+                initial_backoff: Duration::from_millis(cli.initial_backoff_ms),
+                ..RetryPolicy::default()
+            });
+        let api = match &cli.record_fixtures {
+            Some(path) => api.with_recorder(FixtureRecorder::open(path).expect("opening fixtures")),
+            None => api,
+        };
+        if cli.no_cache {
+            api
+        } else {
+            api.with_cache(
+                EvaluationCache::open(&cli.cache_dir, &cli.endpoint).expect("opening cache"),
+            )
+        }
+    });
 
     let start = Instant::now();
-    tokio::runtime::Runtime::new().unwrap().block_on(async {
-        run(&api, &indata).await;
-    });
-    tracing::info!(num_calls = ?api.num_calls(), elapsed = ?start.elapsed());
+    let result =
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(run(api.as_ref(), &indata, cli.command));
+    tracing::info!(num_calls = ?api.as_ref().map(Api::num_calls), elapsed = ?start.elapsed());
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
 }
 
-async fn run(api: &Api, indata: &InputData) {
-    const WHICH: &str = "localopt";
-    match WHICH {
-        "sweep" => {
-            let results = sweep(api, indata).await;
+async fn run(
+    api: Option<&Api>,
+    indata: &InputData,
+    command: Command,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Sweep { customer } => {
+            let results = sweep(api.unwrap(), indata, customer.as_deref()).await;
             println!();
             let mut best_tot_score = 0.0;
             for (rate, award, score, whitebox_score) in results {
@@ -54,44 +296,215 @@ async fn run(api: &Api, indata: &InputData) {
                     ""
                 };
                 println!("{score} @ rate={rate:.3} award={award:?}{record}");
-                if score.environmental_impact != whitebox_score.environmental_impact
-                    || (score.happiness_score - whitebox_score.happiness_score).abs() > 0.4
-                    || (score.total_score - whitebox_score.total_score).abs() > 1e-5
-                {
-                    eprintln!("mismatch\n    real={score:?}\nwhitebox={whitebox_score:?}\n");
-                }
+                report_mismatch(&score, &whitebox_score);
             }
         }
-        "localopt" => {
-            let (expected_score, submission) = opt::blackbox_locally_optimized_submission(indata);
-            let whitebox_score = whitebox::simulate(&indata, &submission);
+        Command::Optimize {
+            opt,
+            output,
+            submit,
+        } => {
+            let (expected_score, submission) =
+                opt::blackbox_locally_optimized_submission(indata, &opt.config());
+            let whitebox_score = whitebox::simulate(indata, &submission);
             dbg!(&expected_score, &whitebox_score);
-            let score = api.evaluate(&indata, &submission).await;
-            dbg!(&expected_score, &whitebox_score, &score);
+            if let Some(output) = output {
+                write_submission(indata, &submission, &output);
+            }
+            if submit {
+                let score = api.unwrap().evaluate(indata, &submission).await?;
+                dbg!(&expected_score, &whitebox_score, &score);
+            }
+        }
+        Command::RemoteOptimize {
+            opt,
+            input,
+            max_calls,
+            output,
+        } => {
+            let submission = match input {
+                Some(input) => read_submission(indata, &input),
+                None => opt::blackbox_locally_optimized_submission(indata, &opt.config()).1,
+            };
+            let (score, submission) =
+                remoteopt::api_refined_submission(api.unwrap(), indata, submission, max_calls)
+                    .await?;
+            println!("{score}");
+            if let Some(output) = output {
+                write_submission(indata, &submission, &output);
+            }
+        }
+        Command::Simulate { input, trace } => {
+            let submission = read_submission(indata, &input);
+            let (score, month_traces) = whitebox::simulate_traced(indata, &submission);
+            // Also on errors, to show what led up to them
+            if let Some(trace) = trace {
+                write_trace(&month_traces, &trace);
+            }
+            println!("{}", score?);
         }
-        "remoteopt" => {
-            todo!()
+        Command::Submit { input } => {
+            let submission = read_submission(indata, &input);
+            println!("{}", api.unwrap().evaluate(indata, &submission).await?);
+        }
+        Command::Compare { input } => {
+            let submission = read_submission(indata, &input);
+            let whitebox_score = whitebox::simulate(indata, &submission);
+            let score = api.unwrap().evaluate(indata, &submission).await?;
+            match &whitebox_score {
+                Ok(whitebox_score) => println!("    real={score}\nwhitebox={whitebox_score}"),
+                Err(err) => println!("    real={score}\nwhitebox: {err}"),
+            }
+            report_mismatch(&score, &whitebox_score);
+        }
+        Command::Audit {
+            submissions,
+            customers_per_submission,
+            seed,
+            output,
+        } => {
+            let report = audit::audit(
+                api.unwrap(),
+                indata,
+                submissions,
+                customers_per_submission,
+                seed,
+            )
+            .await?;
+            for discrepancy in &report.discrepancies {
+                println!("{discrepancy}");
+            }
+            println!(
+                "{} of {} submissions diverge, {} rejected",
+                report.discrepancies.len(),
+                report.num_submissions,
+                report.num_rejected,
+            );
+            if let Some(output) = output {
+                fs::write(output, serde_json::to_string_pretty(&report).unwrap())
+                    .expect("writing report");
+            }
+        }
+        Command::BenchDp { customers } => {
+            let customers = &indata.map.customers[..customers
+                .unwrap_or(indata.map.customers.len())
+                .min(indata.map.customers.len())];
+            let months_game = indata.map.game_length_in_months;
+            let awards_available = indata.awards_by_id();
+            // Shorter loans leave more months after the payback to plan awards for, and longer
+            // ones more months of interest, so time both ends of the months limit
+            for fraction in [0.1, 0.25, 0.5, 1.0] {
+                let start = Instant::now();
+                let timings: Vec<(Duration, usize)> = customers
+                    .par_iter()
+                    .map(|customer| {
+                        let start = Instant::now();
+                        let personality = &indata.personalities[&customer.personality];
+                        let months_limit = personality.months_limit_multiplier * months_game;
+                        let front = whitebox::simulate_kernel_dp_optimal_awards(
+                            &indata.rules,
+                            customer,
+                            personality,
+                            personality.accepted_min_interest,
+                            ((fraction * months_limit as f64) as usize).max(1),
+                            months_game,
+                            &awards_available,
+                        );
+                        (start.elapsed(), front.len())
+                    })
+                    .collect();
+                let slowest = timings.iter().map(|(t, _)| *t).max().unwrap_or_default();
+                let front_sizes: usize = timings.iter().map(|(_, n)| n).sum();
+                println!(
+                    "months {fraction} of the limit: {} customers in {:?}, slowest {slowest:?}, \
+                     {front_sizes} pareto optimal award plans",
+                    customers.len(),
+                    start.elapsed(),
+                );
+            }
         }
-        _ => unimplemented!(),
     };
+    Ok(())
+}
+
+fn report_mismatch(score: &Score, whitebox_score: &Result<Score, SimulationError>) {
+    if whitebox_score
+        .as_ref()
+        .is_ok_and(|whitebox_score| !audit::diverges(score, whitebox_score))
+    {
+        return;
+    }
+    eprintln!("mismatch\n    real={score:?}\nwhitebox={whitebox_score:?}\n");
 }
 
-async fn sweep(api: &Api, indata: &InputData) -> Vec<(f64, Option<&'static str>, Score, Score)> {
+fn write_submission(
+    indata: &InputData,
+    submission: &[(&'static str, CustomerSubmission)],
+    path: &PathBuf,
+) {
+    let request = Request::create_of_per_customer(indata, submission);
+    fs::write(path, serde_json::to_string_pretty(&request).unwrap()).expect("writing submission");
+}
+
+fn write_trace(month_traces: &[MonthTrace], path: &PathBuf) {
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        // Quotes customer and award names containing commas or quotes
+        let mut writer = csv::Writer::from_path(path).expect("writing trace");
+        for month_trace in month_traces {
+            writer.serialize(month_trace).expect("writing trace");
+        }
+        writer.flush().expect("writing trace");
+    } else {
+        fs::write(path, serde_json::to_string_pretty(month_traces).unwrap())
+            .expect("writing trace");
+    }
+}
+
+fn read_submission(indata: &InputData, path: &PathBuf) -> Vec<(&'static str, CustomerSubmission)> {
+    let request: &'static str = fs::read_to_string(path).expect("reading submission").leak();
+    let request: Request = serde_json::from_str(request).expect("parsing submission");
+    assert_eq!(
+        request.map_name, indata.map.name,
+        "submission is for another map"
+    );
+    request.to_per_customer()
+}
+
+async fn sweep(
+    api: &Api,
+    indata: &InputData,
+    customer: Option<&str>,
+) -> Vec<(
+    f64,
+    Option<&'static str>,
+    Score,
+    Result<Score, SimulationError>,
+)> {
     let rates = linspace(0.0, 6.0, 121);
     //let awards = iter::once(None).chain(indata.awards.keys().copied().map(Some));
     let awards = iter::once(None);
     let parameters = rates.cartesian_product(awards);
 
     return futures::future::join_all(parameters.map(|(rate, award)| async move {
-        let submission = parameterized(indata, rate, award);
-        let score = api.evaluate(&indata, &submission).await;
-        let whitebox_score = whitebox::simulate(&indata, &submission);
-        (rate, award, score, whitebox_score)
+        let submission = parameterized(indata, customer, rate, award);
+        let score = match api.evaluate(indata, &submission).await {
+            Ok(score) => score,
+            Err(err) => {
+                tracing::error!(%err, rate, award, "skipping");
+                return None;
+            }
+        };
+        let whitebox_score = whitebox::simulate(indata, &submission);
+        Some((rate, award, score, whitebox_score))
     }))
-    .await;
+    .await
+    .into_iter()
+    .flatten()
+    .collect();
 
     fn parameterized(
         indata: &InputData,
+        customer: Option<&str>,
         rate: f64,
         award: Option<&'static str>,
     ) -> Vec<(&'static str, CustomerSubmission)> {
@@ -99,14 +512,14 @@ async fn sweep(api: &Api, indata: &InputData) -> Vec<(f64, Option<&'static str>,
             .map
             .customers
             .iter()
-            .filter(|c| c.name == "Gary Kreiger")
+            .filter(|c| customer.is_none_or(|name| c.name == name))
             .map(|customer| {
                 let personality = indata.personalities.get(&customer.personality).unwrap();
                 (
                     customer.name,
                     CustomerSubmission {
                         months_to_pay_back_loan: //personality.months_limit_multiplier
-                            1* indata.map.game_length_in_months,
+                            indata.map.game_length_in_months,
                         yearly_interest_rate: rate.clamp(
                             personality.accepted_min_interest,
                             personality.accepted_max_interest,
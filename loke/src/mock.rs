@@ -0,0 +1,149 @@
+use crate::{
+    api::InputData,
+    model::{GameRules, Request, Response},
+    whitebox,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, time::Instant};
+
+#[derive(Clone, Debug)]
+pub struct MockConfig {
+    pub data_dir: PathBuf,
+    /// Rules of every map played
+    pub rules: GameRules,
+    /// Respond 429 to calls arriving sooner than this after the previous accepted call
+    pub min_interval: Duration,
+    /// Delay before responding to an accepted call
+    pub latency: Duration,
+    /// Statuses answered in turn to the first accepted calls, with a body that is not a
+    /// `Response`, e.g. 503 to exercise retries or 200 to exercise decoding
+    pub faults: Vec<StatusCode>,
+}
+
+struct MockState {
+    config: MockConfig,
+    maps: Mutex<BTreeMap<String, &'static InputData>>,
+    last_call: Mutex<Option<Instant>>,
+    faults: Mutex<VecDeque<StatusCode>>,
+    num_games: AtomicUsize,
+}
+
+type Rejection = (StatusCode, String);
+
+/// Stand-in for the game server, scoring submissions with `whitebox::simulate`
+pub async fn serve(listener: TcpListener, config: MockConfig) -> io::Result<()> {
+    let state = Arc::new(MockState {
+        faults: Mutex::new(config.faults.iter().copied().collect()),
+        config,
+        maps: Mutex::new(BTreeMap::new()),
+        last_call: Mutex::new(None),
+        num_games: AtomicUsize::new(0),
+    });
+    let app = Router::new().route("/game", post(game)).with_state(state);
+    axum::serve(listener, app).await
+}
+
+async fn game(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<Response>, Rejection> {
+    if !headers.contains_key("x-api-key") {
+        return Err((StatusCode::UNAUTHORIZED, "Missing x-api-key".to_owned()));
+    }
+    {
+        let now = Instant::now();
+        let mut last_call = state.last_call.lock().unwrap();
+        if last_call.is_some_and(|last| now < last + state.config.min_interval) {
+            return Err((StatusCode::TOO_MANY_REQUESTS, String::new()));
+        }
+        *last_call = Some(now);
+    }
+    if let Some(status) = state.faults.lock().unwrap().pop_front() {
+        return Err((status, "injected fault".to_owned()));
+    }
+    tokio::time::sleep(state.config.latency).await;
+
+    let request: Request = serde_json::from_str(body.leak())
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    let indata = state.load(request.map_name)?;
+    let submission = request.to_per_customer();
+    let score = whitebox::simulate(indata, &submission)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+    Ok(Json(Response {
+        game_id: format!("mock-{}", state.num_games.fetch_add(1, Ordering::Relaxed)),
+        message: (),
+        score,
+    }))
+}
+
+impl MockState {
+    fn load(&self, map_name: &str) -> Result<&'static InputData, Rejection> {
+        let mut maps = self.maps.lock().unwrap();
+        if let Some(indata) = maps.get(map_name) {
+            return Ok(indata);
+        }
+        let indata =
+            InputData::load_with_rules(&self.config.data_dir, map_name, self.config.rules.clone())
+                .map_err(|errors| {
+                    let errors: Vec<_> = errors.iter().map(|err| err.to_string()).collect();
+                    (StatusCode::BAD_REQUEST, errors.join("\n"))
+                })?;
+        let indata: &'static InputData = Box::leak(Box::new(indata));
+        maps.insert(map_name.to_owned(), indata);
+        Ok(indata)
+    }
+}
+
+#[tokio::test]
+async fn test_submit_to_mock_server() {
+    use crate::{api::Api, test_util};
+
+    let endpoint = test_util::spawn_mock_server(MockConfig {
+        // Slower than `Api::API_DELAY` to exercise the 429 path
+        min_interval: Api::API_DELAY + Duration::from_millis(50),
+        latency: Duration::from_millis(10),
+        ..test_util::mock_config(GameRules::default())
+    })
+    .await;
+
+    let indata = test_util::load("Gothenburg");
+    let submission = test_util::whole_game_submissions(&indata, test_util::min_rate, |i| {
+        (i % 3 == 2).then_some("IkeaCheck")
+    });
+
+    let api = Arc::new(Api::with_api_key("mock").with_endpoint(endpoint));
+    let indata = Arc::new(indata);
+    let tasks: Vec<_> = (0..3)
+        .map(|_| {
+            let (api, indata, submission) = (api.clone(), indata.clone(), submission.clone());
+            tokio::spawn(async move { api.evaluate(&indata, &submission).await })
+        })
+        .collect();
+    let expected = whitebox::simulate(&indata, &submission).unwrap();
+    for task in tasks {
+        assert_eq!(task.await.unwrap().unwrap(), expected);
+    }
+    assert_eq!(api.num_calls(), 3);
+
+    let unknown_customer = vec![("Nobody", submission[0].1.clone())];
+    assert!(matches!(
+        api.evaluate(&indata, &unknown_customer).await,
+        Err(crate::api::ApiError::Validation { .. })
+    ));
+}
@@ -19,6 +19,7 @@ pub struct Customer {
     #[serde(deserialize_with = "leak_string")]
     pub name: &'static str,
     pub loan: Loan,
+    #[serde(default)]
     pub gender: String,
     pub personality: String,
     pub capital: f64,
@@ -76,8 +77,12 @@ pub struct Award {
     pub id: std::num::NonZeroU8,
     pub cost: f64,
     pub base_happiness: f64,
+    /// Fraction of the month's interest that the bank pays for. The data file takes
+    /// precedence, `GameRules::award_interest_rebates` fills in awards it has none for.
+    #[serde(default)]
+    pub interest_rebate: Option<f64>,
 }
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 #[serde(deny_unknown_fields)]
 pub struct Proposal {
@@ -85,7 +90,7 @@ pub struct Proposal {
     pub months_to_pay_back_loan: usize,
     pub yearly_interest_rate: f64,
 }
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 #[serde(deny_unknown_fields)]
 pub struct Action {
@@ -93,7 +98,7 @@ pub struct Action {
     pub type_: &'static str,
     pub award: &'static str,
 }
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 #[serde(deny_unknown_fields)]
 pub struct Request {
@@ -101,7 +106,7 @@ pub struct Request {
     pub proposals: Vec<Proposal>,
     pub iterations: Vec<BTreeMap<&'static str, Action>>,
 }
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Response {
@@ -110,7 +115,7 @@ pub struct Response {
     pub message: (),
     pub score: Score,
 }
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub struct Score {
@@ -123,6 +128,67 @@ pub struct Score {
     /// Sum of the 3 sub-scores
     pub total_score: f64,
 }
+
+/// Constants of the game rules, so that they can be changed between competition rounds or to
+/// test hypotheses about the server without touching the simulator
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct GameRules {
+    /// Round or revision of the rules, e.g. `2024`
+    pub version: String,
+    pub student_loan_cost: f64,
+    /// Student loans are paid in months that are a multiple of this
+    pub student_loan_interval_months: usize,
+    pub cost_per_kid: f64,
+    /// Fraction of the home mortgage paid every month
+    pub mortgage_factor: f64,
+    /// Marks at which a customer goes bankrupt
+    pub marks_limit: usize,
+    /// Happiness a bankrupt customer ends with, replacing all previous happiness
+    pub bankruptcy_happiness: f64,
+    /// Happiness lost for every missed loan payment short of bankruptcy
+    pub mark_happiness_penalty: f64,
+    /// Happiness multiplier lost for every recent award
+    pub award_fatigue: f64,
+    /// Recent awards counted towards fatigue
+    pub max_awards_in_a_row: usize,
+    /// Happiness multiplier when the same award is given this many times in a row
+    pub repeat_happiness_multiplier: f64,
+    pub repeat_award_limit: usize,
+    /// Months without awards before the customer starts losing happiness
+    pub no_award_grace_months: usize,
+    /// Happiness lost per month without awards once past the grace period
+    pub no_award_penalty_per_month: f64,
+    /// Fraction of the month's interest paid for by the bank, by award name
+    pub award_interest_rebates: BTreeMap<String, f64>,
+}
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            version: "2024".to_owned(),
+            student_loan_cost: 2000.0,
+            student_loan_interval_months: 3,
+            cost_per_kid: 2000.0,
+            mortgage_factor: 0.001,
+            marks_limit: 3,
+            bankruptcy_happiness: -500.0,
+            mark_happiness_penalty: 50.0,
+            award_fatigue: 0.2,
+            max_awards_in_a_row: 5,
+            repeat_happiness_multiplier: -1.0,
+            repeat_award_limit: 3,
+            no_award_grace_months: 3,
+            no_award_penalty_per_month: 500.0,
+            award_interest_rebates: [("NoInterestRate", 1.0), ("HalfInterestRate", 0.5)]
+                .into_iter()
+                .map(|(name, rebate)| (name.to_owned(), rebate))
+                .collect(),
+        }
+    }
+}
+
 fn non_zero_u8_max() -> NonZeroU8 {
     NonZeroU8::MAX
 }
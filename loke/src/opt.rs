@@ -1,394 +1,691 @@
 use crate::{
-    api::{CustomerSubmission, InputData},
-    model::{Award, Customer, Personality},
+    api::{CustomerSubmission, InputData, LoadError},
+    model::Award,
+    solver::{CustomerProblem, SolverKind},
 };
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-const USE_VERY_SLOW_BUT_GOOD_DP: bool = false;
-//const NUM_PARTICLES: usize = 20;
-//const MAX_ITERS: u64 = 10000;
-//const AWARD_CONF_TTL: usize = 1000;
-const NUM_PARTICLES: usize = 200;
-const MAX_ITERS: u64 = 1000; // 0
-const AWARD_CONF_TTL: usize = 100_000;
-
-#[derive(Clone)]
-struct Param(Vec<f64>, usize);
-
-impl argmin_math::ArgminAdd<Self, Self> for Param {
-    fn add(&self, other: &Self) -> Self {
-        Self(self.0.add(&other.0), self.1.clone())
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Preset {
+    /// Heuristic awards, for trying things out on big maps
+    Quick,
+    Default,
+    /// Large swarm that also re-plans awards while searching, for final submissions
+    Exhaustive,
 }
-impl argmin_math::ArgminSub<Self, Self> for Param {
-    fn sub(&self, other: &Self) -> Self {
-        Self(self.0.sub(&other.0), self.1.clone())
-    }
-}
-impl argmin_math::ArgminMinMax for Param {
-    fn min(x: &Self, y: &Self) -> Self {
-        Self(argmin_math::ArgminMinMax::min(&x.0, &y.0), x.1.clone())
-    }
-    fn max(x: &Self, y: &Self) -> Self {
-        Self(argmin_math::ArgminMinMax::max(&x.0, &y.0), x.1.clone())
-    }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct OptConfig {
+    /// Optimizer of the (rate, months) of every customer
+    pub solver: SolverKind,
+    pub num_particles: usize,
+    pub max_iters: u64,
+    /// Evaluations of a particle before its awards are re-planned with the exact DP
+    pub award_conf_ttl: usize,
+    /// Plan the awards of the final (rate, months) of every customer with the exact DP rather
+    /// than trying a few alternating award patterns
+    pub use_exact_award_dp: bool,
+    /// Rates tried per months value by the grid solver
+    pub grid_rate_steps: usize,
+    /// Most (rate, months) of every customer that awards are planned for, the solver's best and
+    /// then cheaper ones along the score and budget front, so that the knapsack can trade score
+    /// for budget across them
+    pub rate_months_candidates: usize,
+    /// Seed of the particle swarm, which is otherwise seeded from entropy
+    pub seed: Option<u64>,
 }
-impl argmin_math::ArgminMul<f64, Self> for Param {
-    fn mul(&self, other: &f64) -> Self {
-        Self(self.0.mul(other), self.1.clone())
+impl OptConfig {
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Quick => Self {
+                solver: SolverKind::MonthsEnumeration,
+                num_particles: 20,
+                max_iters: 100,
+                award_conf_ttl: 100_000,
+                use_exact_award_dp: false,
+                grid_rate_steps: 100,
+                rate_months_candidates: 8,
+                seed: None,
+            },
+            Preset::Default => Self {
+                solver: SolverKind::MonthsEnumeration,
+                num_particles: 200,
+                max_iters: 1000,
+                award_conf_ttl: 100_000,
+                use_exact_award_dp: true,
+                grid_rate_steps: 100,
+                rate_months_candidates: 8,
+                seed: None,
+            },
+            Preset::Exhaustive => Self {
+                solver: SolverKind::ParticleSwarm,
+                num_particles: 500,
+                max_iters: 5000,
+                award_conf_ttl: 1000,
+                use_exact_award_dp: true,
+                grid_rate_steps: 100,
+                rate_months_candidates: 8,
+                seed: None,
+            },
+        }
     }
-}
-impl argmin_math::ArgminRandom for Param {
-    fn rand_from_range<R: argmin_math::Rng>(min: &Self, max: &Self, rng: &mut R) -> Self {
-        Self(
-            Vec::<f64>::rand_from_range(&min.0, &max.0, rng),
-            u64::rand_from_range(&u64::MIN, &u64::MAX, rng) as usize,
-        )
+
+    /// Settings missing from the file are taken from the default preset
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        crate::api::read_json(path.to_owned())
     }
 }
-impl argmin_math::ArgminZeroLike for Param {
-    fn zero_like(&self) -> Self {
-        Self(vec![0.0; self.0.len()], self.1)
+impl Default for OptConfig {
+    fn default() -> Self {
+        Self::preset(Preset::Default)
     }
 }
 
 pub fn blackbox_locally_optimized_submission(
     indata: &InputData,
+    config: &OptConfig,
 ) -> (f64, Vec<(&'static str, CustomerSubmission)>) {
-    #[derive(Debug)]
-    struct BlackboxOpt {
-        customer: Customer,
-        personality: Personality,
-        game_length_in_months: usize,
-        award_available: [(&'static str, Award, f64); 6],
-        id_to_awards_ttl: dashmap::DashMap<usize, (Vec<Option<(Award, f64)>>, usize)>,
-    }
-    fn param_to_rate_months(p: &Vec<f64>, personality: &Personality) -> (f64, usize) {
-        let p0 = if personality.accepted_max_interest > 1.0 {
-            p[0].powf(8.0)
-        } else {
-            p[0]
-        };
-        let rate =
-            p0 * personality.accepted_max_interest + (1.0 - p0) * personality.accepted_min_interest;
-        let months = (p[1] * personality.months_limit_multiplier as f64).round() as usize;
-        (rate, months)
-    }
-    impl argmin::core::CostFunction for BlackboxOpt {
-        type Param = Param;
-        type Output = f64;
-        fn cost(&self, p: &Self::Param) -> Result<Self::Output, argmin::core::Error> {
-            let (rate, months) = param_to_rate_months(&p.0, &self.personality);
-            let mut entry = self
-                .id_to_awards_ttl
-                .entry(p.1)
-                .or_insert_with(|| (vec![None; self.game_length_in_months], AWARD_CONF_TTL));
-            let (awards, ttl) = entry.value_mut();
-            if *ttl == 0 {
-                let aws = crate::whitebox::simulate_kernel_dp_optimal_awards(
-                    &self.customer,
-                    &self.personality,
-                    rate,
-                    months,
-                    self.game_length_in_months,
-                    &self.award_available,
-                )
-                .into_iter()
-                .max_by(|(s1, _, _), (s2, _, _)| f64::total_cmp(&s1, &s2))
-                .unwrap_or_else(|| (0.0, 0.0, vec![None; self.game_length_in_months]));
-
-                *awards = aws
-                    .2
-                    .into_iter()
-                    .map(|a| {
-                        a.map(|aa| {
-                            let t = self.award_available[(aa.get() as usize) - 1];
-                            (t.1, t.2)
-                        })
-                    })
-                    .collect();
-                *ttl = AWARD_CONF_TTL;
-            } else {
-                *ttl -= 1;
-            }
-            let (score, _budget_required, _bankrupt) = crate::whitebox::simulate_simplified_kernel(
-                &self.customer,
-                &self.personality,
-                rate,
-                months,
-                self.game_length_in_months,
-                awards,
-            );
-            Ok(-score)
-        }
-    }
-
+    let solver = config.solver.solver(config);
+    let progress = Progress::new(indata.map.customers.len());
     let ret = indata
         .map
         .customers
         .par_iter()
         .map(|customer| {
-            // HACK: Jitter to workaround Slogger not locking output
-            std::thread::sleep(std::time::Duration::from_millis(
-                customer.capital as u64 % 123,
-            ));
+            let award_available = indata.awards_by_id();
+            let problem = CustomerProblem::new(indata, customer, &award_available);
+            let personality = problem.personality;
+            let candidates = solver.candidates(&problem, config.rate_months_candidates);
+            let plans = pareto_front(
+                candidates
+                    .into_iter()
+                    .flat_map(|(rate, months)| -> Vec<Plan> {
+                        if config.use_exact_award_dp {
+                            crate::whitebox::simulate_kernel_dp_optimal_awards(
+                                &indata.rules,
+                                customer,
+                                personality,
+                                rate,
+                                months,
+                                indata.map.game_length_in_months,
+                                &award_available,
+                            )
+                            .into_iter()
+                            .map(|(score, cost, awards)| {
+                                tracing::debug!(customer.name, rate, months, ?awards, score, cost);
+                                (
+                                    (
+                                        customer.name,
+                                        CustomerSubmission {
+                                            months_to_pay_back_loan: months,
+                                            yearly_interest_rate: rate,
+                                            awards: awards
+                                                .into_iter()
+                                                .map(|a| {
+                                                    a.map(|aa| {
+                                                        award_available[(aa.get() as usize) - 1].0
+                                                    })
+                                                })
+                                                .collect(),
+                                        },
+                                    ),
+                                    score,
+                                    cost,
+                                )
+                            })
+                            .filter(|(_, _, cost)| *cost <= indata.map.budget)
+                            .collect()
+                        } else {
+                            let num_awards = award_available.len();
+                            (0..num_awards * num_awards)
+                                .filter_map(|idx| {
+                                    let a = idx % num_awards;
+                                    let b = idx / num_awards;
+                                    if a == b {
+                                        return None;
+                                    }
+                                    let mut awards: Vec<Option<&str>> =
+                                        vec![None; indata.map.game_length_in_months];
+                                    let mut sim_awards: Vec<Option<Award>> =
+                                        vec![None; indata.map.game_length_in_months];
+                                    let mut lasta = true;
+                                    for i in 0..indata.map.game_length_in_months {
+                                        if i % 4 != 3 {
+                                            continue;
+                                        }
+                                        let xx = if !lasta {
+                                            lasta = true;
+                                            award_available[a]
+                                        } else {
+                                            lasta = false;
+                                            award_available[b]
+                                        };
+                                        awards[i] = Some(xx.0);
+                                        sim_awards[i] = Some(xx.1);
+                                    }
 
-            let personality = &indata.personalities[&customer.personality];
-            let award_available = {
-                let entry = indata.awards.first_key_value().unwrap();
-                let mut ret = [(*entry.0, *entry.1, 0.0); 6];
-                for (&n, &a) in indata.awards.iter() {
-                    let d = match n {
-                        "NoInterestRate" => 1.0,
-                        "HalfInterestRate" => 0.5,
-                        _ => 0.0,
-                    };
-                    ret[a.id.get() as usize - 1] = (n, a, d);
-                }
-                ret
-            };
-            let opt = BlackboxOpt {
-                customer: customer.clone(),
-                personality: personality.clone(),
-                game_length_in_months: indata.map.game_length_in_months,
-                award_available: award_available.clone(),
-                id_to_awards_ttl: dashmap::DashMap::new(),
-            };
-            let award_available = opt.award_available.clone();
-            let solver = argmin::solver::particleswarm::ParticleSwarm::<Param, f64, _>::new(
-                (
-                    Param(vec![0.0, 0.0], 0),
-                    Param(
-                        vec![1.0, indata.map.game_length_in_months as f64],
-                        usize::MAX,
-                    ),
-                ),
-                NUM_PARTICLES,
+                                    let (score, budget_required, bankruptcy_at) =
+                                        crate::whitebox::simulate_simplified_kernel(
+                                            &indata.rules,
+                                            customer,
+                                            personality,
+                                            rate,
+                                            months,
+                                            indata.map.game_length_in_months,
+                                            &sim_awards,
+                                        );
+                                    let bankruptcy_at = if bankruptcy_at == -1 {
+                                        String::new()
+                                    } else {
+                                        format!("bankrupt_at={}", bankruptcy_at)
+                                    };
+                                    tracing::debug!(
+                                        customer.name,
+                                        rate,
+                                        months,
+                                        score,
+                                        budget_required,
+                                        "{}",
+                                        bankruptcy_at,
+                                    );
+                                    Some((
+                                        (
+                                            customer.name,
+                                            CustomerSubmission {
+                                                months_to_pay_back_loan: months,
+                                                yearly_interest_rate: rate,
+                                                awards: awards.into(),
+                                            },
+                                        ),
+                                        score,
+                                        budget_required,
+                                    ))
+                                })
+                                .filter(|(_, score, cost)| {
+                                    *score > 0.0 && *cost <= indata.map.budget
+                                })
+                                .collect()
+                        }
+                    }),
+                0.0,
             );
-            let res = argmin::core::Executor::new(opt, solver)
-                .add_observer(
-                    argmin_observer_slog::SlogLogger::term(),
-                    argmin::core::observers::ObserverMode::NewBest,
-                )
-                .configure(|state| state.max_iters(MAX_ITERS))
-                .run()
-                .unwrap();
-            //dbg!(res.problem());
-            //dbg!(&res.state().best_individual);
-            let (rate, months) = param_to_rate_months(
-                &res.state().best_individual.as_ref().unwrap().position.0,
-                &personality.clone(),
+            progress.customer_done(
+                customer.name,
+                plans.iter().map(|(_, score, _)| *score).reduce(f64::max),
             );
-            if USE_VERY_SLOW_BUT_GOOD_DP {
-                crate::whitebox::simulate_kernel_dp_optimal_awards(
-                    &customer,
-                    &personality,
-                    rate,
-                    months,
-                    indata.map.game_length_in_months,
-                    &award_available,
-                )
-                .into_iter()
-                .map(|(score, cost, awards)| {
-                    tracing::info!(customer.name, rate, months, ?awards, score, cost);
-                    // TODO: Cost rounding could be incorrect
-                    (
-                        (
-                            customer.name,
-                            CustomerSubmission {
-                                months_to_pay_back_loan: months,
-                                yearly_interest_rate: rate,
-                                awards: awards
-                                    .into_iter()
-                                    .map(|a| a.map(|aa| award_available[(aa.get() as usize) - 1].0))
-                                    .collect(),
-                            },
-                        ),
-                        score,
-                        cost.round() as usize,
-                    )
+            plans
+        })
+        .collect();
+
+    let (ret, ret_score) = select_portfolio(indata, ret);
+    (ret_score, ret)
+}
+
+type Plan = ((&'static str, CustomerSubmission), f64, f64);
+
+/// (item, score, cost) scoring above `min_score` that no other beats on both score and cost,
+/// by increasing cost
+pub(crate) fn pareto_front<T>(
+    items: impl IntoIterator<Item = (T, f64, f64)>,
+    min_score: f64,
+) -> Vec<(T, f64, f64)> {
+    let mut items: Vec<_> = items.into_iter().collect();
+    items.sort_by(|(_, s1, c1), (_, s2, c2)| f64::total_cmp(c1, c2).then(f64::total_cmp(s2, s1)));
+    let mut front: Vec<(T, f64, f64)> = vec![];
+    for item in items {
+        if item.1 > front.last().map_or(min_score, |(_, score, _)| *score) {
+            front.push(item);
+        }
+    }
+    front
+}
+
+// Starts from the knapsack over each plan's peak budget, which is safe but pessimistic since
+// customers peak in different months and early interest funds later awards. Then upgrades
+// customers greedily while the summed month by month budget profiles stay within the budget.
+fn select_portfolio(
+    indata: &InputData,
+    plans: Vec<Vec<Plan>>,
+) -> (Vec<(&'static str, CustomerSubmission)>, f64) {
+    let months_game = indata.map.game_length_in_months;
+    let profiles: Vec<Vec<Vec<f64>>> = plans
+        .par_iter()
+        .map(|customer_plans| {
+            customer_plans
+                .iter()
+                .map(|((name, submission), _, _)| {
+                    let customer = indata
+                        .map
+                        .customers
+                        .iter()
+                        .find(|c| c.name == *name)
+                        .unwrap();
+                    let awards: Vec<Option<Award>> = submission
+                        .awards
+                        .iter()
+                        .map(|award| award.map(|name| indata.awards[name]))
+                        .collect();
+                    crate::whitebox::Contract {
+                        rules: &indata.rules,
+                        customer,
+                        personality: &indata.personalities[&customer.personality],
+                        yearly_interest_rate: submission.yearly_interest_rate,
+                        months_to_pay_back_loan: submission.months_to_pay_back_loan,
+                    }
+                    .budget_profile(months_game, &awards)
                 })
                 .collect()
-            } else {
-                (0..36)
-                    .filter_map(|idx| {
-                        let a = idx % 6;
-                        let b = idx / 6;
-                        if a == b {
-                            return None;
-                        }
-                        let mut awards: Vec<Option<&str>> =
-                            vec![None; indata.map.game_length_in_months];
-                        let mut sim_awards: Vec<Option<(Award, f64)>> =
-                            vec![None; indata.map.game_length_in_months];
-                        let mut lasta = true;
-                        for i in 0..indata.map.game_length_in_months {
-                            if i % 4 != 3 {
-                                continue;
-                            }
-                            let xx = if !lasta {
-                                lasta = true;
-                                award_available[a]
-                            } else {
-                                lasta = false;
-                                award_available[b]
-                            };
-                            awards[i] = Some(xx.0);
-                            sim_awards[i] = Some((
-                                xx.1,
-                                match xx.0 {
-                                    "NoInterestRate" => 1.0,
-                                    "HalfInterestRate" => 0.5,
-                                    _ => 0.0,
-                                },
-                            ));
-                        }
+        })
+        .collect();
 
-                        let (score, budget_required, bankruptcy_at) =
-                            crate::whitebox::simulate_simplified_kernel(
-                                &customer,
-                                personality,
-                                rate,
-                                months,
-                                indata.map.game_length_in_months,
-                                &sim_awards,
-                            );
-                        let bankruptcy_at = if bankruptcy_at == -1 {
-                            String::new()
-                        } else {
-                            format!("bankrupt_at={}", bankruptcy_at)
-                        };
-                        tracing::info!(
-                            customer.name,
-                            rate,
-                            months,
-                            score,
-                            budget_required,
-                            "{}",
-                            bankruptcy_at,
-                        );
-                        Some((
-                            (
-                                customer.name,
-                                CustomerSubmission {
-                                    months_to_pay_back_loan: months,
-                                    yearly_interest_rate: rate,
-                                    awards: awards.into(),
-                                },
-                            ),
-                            score,
-                            round_pre_knapsack(budget_required, true),
-                        ))
-                    })
-                    .filter(|(_, score, cost)| *score > 0.0 && *cost < indata.map.budget as usize)
-                    .collect::<Vec<(_, f64, usize)>>()
-            }
+    let indexed: Vec<Vec<_>> = plans
+        .iter()
+        .enumerate()
+        .map(|(c, customer_plans)| {
+            customer_plans
+                .iter()
+                .enumerate()
+                .map(|(v, (_, score, cost))| ((c, v), *score, *cost))
+                .collect()
         })
         .collect();
+    let (picked, _) = knapsack(indexed, indata.map.budget);
+    let mut chosen: Vec<Option<usize>> = vec![None; plans.len()];
+    for (c, v) in picked {
+        chosen[c] = Some(v);
+    }
+
+    let plans = &plans;
+    let budget = indata.map.budget;
+    let profile = |c: usize, v: Option<usize>, i: usize| v.map_or(0.0, |v| profiles[c][v][i]);
+    let score_of = |c: usize, v: Option<usize>| v.map_or(0.0, |v| plans[c][v].1);
+    let mut used: Vec<f64> = (0..months_game)
+        .map(|i| (0..plans.len()).map(|c| profile(c, chosen[c], i)).sum())
+        .collect();
+    let switch = |used: &mut [f64], chosen: &mut [Option<usize>], c: usize, w: Option<usize>| {
+        for (i, used) in used.iter_mut().enumerate() {
+            *used += profile(c, w, i) - profile(c, chosen[c], i);
+        }
+        chosen[c] = w;
+    };
+    // Customer giving the least score per budget tied up in month `i`
+    let least_efficient = |chosen: &[Option<usize>], i: usize| {
+        (0..plans.len())
+            .filter(|&c| profile(c, chosen[c], i) > 0.0)
+            .min_by(|&c, &d| {
+                f64::total_cmp(
+                    &(score_of(c, chosen[c]) / profile(c, chosen[c], i)),
+                    &(score_of(d, chosen[d]) / profile(d, chosen[d], i)),
+                )
+            })
+    };
+
+    // The knapsack only looks at the start of every month, so its choice may overshoot
+    while let Some(i) = (0..months_game).find(|&i| used[i] >= budget) {
+        let Some(c) = least_efficient(&chosen, i) else {
+            break;
+        };
+        switch(&mut used, &mut chosen, c, None);
+    }
 
-    // NOTE: Incorrect if fractional loans
-    fn round_pre_knapsack(x: f64, cceil: bool) -> usize {
-        // TODO: Maybe ceil
-        if cceil {
-            (x.ceil() / 10.0) as usize
-        } else {
-            (x.floor() / 10.0) as usize
+    loop {
+        let mut moves: Vec<(f64, usize, usize)> = chosen
+            .iter()
+            .enumerate()
+            .flat_map(|(c, v)| {
+                let current = score_of(c, *v);
+                (0..plans[c].len())
+                    .filter(move |w| Some(*w) != *v && plans[c][*w].1 > current)
+                    .map(move |w| (plans[c][w].1 - current, c, w))
+            })
+            .collect();
+        moves.sort_by(|(g1, _, _), (g2, _, _)| f64::total_cmp(g2, g1));
+        let mut improved = false;
+        for (_, c, w) in moves {
+            // Earlier moves of this pass may have changed the customer's plan
+            if plans[c][w].1 <= score_of(c, chosen[c]) {
+                continue;
+            }
+            if (0..months_game)
+                .all(|i| used[i] - profile(c, chosen[c], i) + profile(c, Some(w), i) < budget)
+            {
+                switch(&mut used, &mut chosen, c, Some(w));
+                improved = true;
+            }
+        }
+        if !improved {
+            break;
         }
     }
 
-    let (ret, ret_score) = knapsack(ret, round_pre_knapsack(indata.map.budget, false));
-    (ret_score, ret)
+    // The profiles bound the budget at every check, so this only guards against rounding
+    let submission = loop {
+        let submission: Vec<_> = chosen
+            .iter()
+            .enumerate()
+            .filter_map(|(c, v)| Some(plans[c][(*v)?].0.clone()))
+            .collect();
+        let Err(crate::whitebox::SimulationError::BudgetExhausted { month }) =
+            crate::whitebox::simulate(indata, &submission)
+        else {
+            break submission;
+        };
+        let Some(c) = least_efficient(&chosen, month) else {
+            tracing::error!(month, "budget exhausted without any customer tying it up");
+            break submission;
+        };
+        tracing::warn!(
+            month,
+            customer = plans[c][0].0 .0,
+            "dropping to fit the budget"
+        );
+        switch(&mut used, &mut chosen, c, None);
+    };
+    let score = (0..plans.len()).map(|c| score_of(c, chosen[c])).sum();
+    (submission, score)
+}
+
+/// Customers optimized so far, logged as each one finishes
+struct Progress {
+    total: usize,
+    start: Instant,
+    /// Customers done and the sum of their best plan scores, ignoring the budget
+    state: Mutex<(usize, f64)>,
 }
+impl Progress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            start: Instant::now(),
+            state: Mutex::new((0, 0.0)),
+        }
+    }
 
-fn gcd(mut x: usize, mut y: usize) -> usize {
-    while x != 0 {
-        (x, y) = (y % x, x);
+    fn customer_done(&self, customer: &str, best_score: Option<f64>) {
+        // Logged under the lock so that `done` and `best_total` never go backwards
+        let mut state = self.state.lock().unwrap();
+        let (done, best_total) = &mut *state;
+        *done += 1;
+        *best_total += best_score.unwrap_or_default().max(0.0);
+        let eta = self
+            .start
+            .elapsed()
+            .mul_f64((self.total - *done) as f64 / *done as f64);
+        tracing::info!(
+            done = *done,
+            total = self.total,
+            customer,
+            best_score,
+            best_total = *best_total,
+            eta = ?Duration::from_secs(eta.as_secs()),
+            "optimized"
+        );
     }
-    y
 }
 
-// O(budget * items.len())
-// NOTE: At integer resolution
-fn knapsack<T: Clone>(mut items: Vec<Vec<(T, f64, usize)>>, mut budget: usize) -> (Vec<T>, f64) {
-    let mut d = budget;
-    for candidates in &items {
-        for &(_, _, c) in candidates {
-            d = gcd(d, c);
+// Sparse Pareto frontier of (cost, score) over the choices of the groups seen so far, so memory
+// depends on the number of non-dominated partial choices rather than on the budget. Partial
+// choices that cannot catch up with a known solution even in the LP relaxation of the remaining
+// groups are pruned, which keeps the frontier small when most customers fit the budget.
+// Items are (opaque, score, cost), at most one per group is chosen.
+fn knapsack<T: Clone>(items: Vec<Vec<(T, f64, f64)>>, budget: f64) -> (Vec<T>, f64) {
+    const ROOT: u32 = u32::MAX;
+
+    // Upper concave hull of the (cost, score) of every group from (0, 0), as (group, cost,
+    // score) increments of decreasing efficiency. Taking increments by efficiency across
+    // groups solves the LP relaxation.
+    let mut increments: Vec<(usize, f64, f64)> = vec![];
+    for (group, variants) in items.iter().enumerate() {
+        let mut points: Vec<(f64, f64)> = variants
+            .iter()
+            .map(|&(_, score, cost)| (cost, score))
+            .filter(|&(cost, score)| score > 0.0 && cost <= budget)
+            .collect();
+        points.sort_by(|(c1, s1), (c2, s2)| f64::total_cmp(c1, c2).then(f64::total_cmp(s2, s1)));
+        let mut hull = vec![(0.0, 0.0)];
+        for p in points {
+            if p.1 <= hull.last().unwrap().1 {
+                continue;
+            }
+            while let [.., a, b] = hull[..] {
+                if (b.1 - a.1) * (p.0 - a.0) <= (p.1 - a.1) * (b.0 - a.0) {
+                    hull.pop();
+                } else {
+                    break;
+                }
+            }
+            hull.push(p);
         }
+        increments.extend(
+            hull.windows(2)
+                .map(|w| (group, w[1].0 - w[0].0, w[1].1 - w[0].1)),
+        );
     }
-    budget /= d;
-    for candidates in &mut items {
-        for it in candidates {
-            it.2 /= d;
+    increments.sort_by(|(_, c1, s1), (_, c2, s2)| f64::total_cmp(&(s2 / c2), &(s1 / c1)));
+
+    // Greedy solution, taking the increments of a group in order while they fit
+    let mut lower_bound = {
+        let (mut cost, mut score) = (0.0, 0.0);
+        let mut blocked = vec![false; items.len()];
+        for &(group, c, s) in &increments {
+            if !blocked[group] && cost + c <= budget {
+                (cost, score) = (cost + c, score + s);
+            } else {
+                blocked[group] = true;
+            }
         }
-    }
+        score
+    };
+
+    // (group, variant, parent) of every choice on some frontier
+    let mut arena: Vec<(u32, u32, u32)> = vec![];
+    // (cost, score, choice), by increasing cost and strictly increasing score
+    let mut frontier: Vec<(f64, f64, u32)> = vec![(0.0, 0.0, ROOT)];
+    for (group, variants) in items.iter().enumerate() {
+        // LP relaxation of the groups after this one, as prefix sums of (cost, score)
+        increments.retain(|&(g, _, _)| g > group);
+        let mut prefix = vec![(0.0, 0.0)];
+        for &(_, c, s) in &increments {
+            let &(pc, ps) = prefix.last().unwrap();
+            prefix.push((pc + c, ps + s));
+        }
+        let upper_bound = |budget_left: f64| {
+            let k = prefix.partition_point(|&(c, _)| c <= budget_left) - 1;
+            let fraction = increments
+                .get(k)
+                .map_or(0.0, |&(_, c, s)| s * (budget_left - prefix[k].0) / c);
+            prefix[k].1 + fraction
+        };
 
-    // items of (opaque, score, cost)
-    // dp[<=category][budget_spent] = (score, last chosen cat, last chosen variant)
-    let mut dp: Vec<Vec<(f64, u32, u32)>> =
-        vec![vec![(0.0, u32::MAX, u32::MAX); budget + 1]; items.len() + 1];
-    for i in 0..items.len() {
-        for b in 0..=budget {
-            // dont buy
-            if dp[i][b].0 > dp[i + 1][b].0 {
-                dp[i + 1][b] = dp[i][b];
+        // (cost, score, parent, variant), variant u32::MAX for not buying from this group
+        let mut next: Vec<(f64, f64, u32, u32)> = frontier
+            .iter()
+            .map(|&(cost, score, choice)| (cost, score, choice, u32::MAX))
+            .collect();
+        for (variant, (_, item_score, item_cost)) in variants.iter().enumerate() {
+            for &(cost, score, choice) in &frontier {
+                if cost + item_cost > budget {
+                    break;
+                }
+                next.push((cost + item_cost, score + item_score, choice, variant as u32));
             }
         }
-        for (variant, (_, score, cost)) in items[i].iter().enumerate() {
-            if budget < *cost {
+        next.sort_by(|(c1, s1, _, _), (c2, s2, _, _)| {
+            f64::total_cmp(c1, c2).then(f64::total_cmp(s2, s1))
+        });
+        lower_bound = next
+            .iter()
+            .map(|&(_, score, _, _)| score)
+            .fold(lower_bound, f64::max);
+        let tolerance = 1e-9 * lower_bound.abs().max(1.0);
+        frontier.clear();
+        for (cost, score, parent, variant) in next {
+            if frontier.last().is_some_and(|&(_, best, _)| score <= best)
+                || score + upper_bound(budget - cost) < lower_bound - tolerance
+            {
                 continue;
             }
-            for b in 0..=budget.saturating_sub(*cost) {
-                // buy additional
-                let cand_score = dp[i][b].0 + score;
-                if cand_score > dp[i + 1][b + cost].0 {
-                    dp[i + 1][b + cost] = (cand_score, i as u32, variant as u32);
-                }
-            }
+            let choice = if variant == u32::MAX {
+                parent
+            } else {
+                arena.push((group as u32, variant, parent));
+                arena.len() as u32 - 1
+            };
+            frontier.push((cost, score, choice));
         }
     }
-    let (winner_score, mut winner_last, mut winner_variant) = dp[items.len()][budget];
-    let mut winner_items = vec![];
-    let mut winner_loop_budget = budget;
-    while winner_last != u32::MAX {
-        winner_items.push(
-            items[winner_last as usize][winner_variant as usize]
-                .0
-                .clone(),
-        );
-        winner_loop_budget -= items[winner_last as usize][winner_variant as usize].2;
 
-        (_, winner_last, winner_variant) = dp[winner_last as usize][winner_loop_budget];
+    let &(_, winner_score, mut choice) = frontier.last().unwrap();
+    let mut winner_items = vec![];
+    while choice != ROOT {
+        let (group, variant, parent) = arena[choice as usize];
+        winner_items.push(items[group as usize][variant as usize].0.clone());
+        choice = parent;
     }
     (winner_items, winner_score)
 }
 
 #[test]
 fn test_knapsack() {
-    assert_eq!(knapsack(vec![vec![(1, 1.23, 1)]], 2), (vec![1], 1.23));
+    assert_eq!(knapsack(vec![vec![(1, 1.23, 1.0)]], 2.0), (vec![1], 1.23));
     assert_eq!(
-        knapsack(vec![vec![(1, 1.23, 1)], vec![(2, 2.23, 1)]], 2),
+        knapsack(vec![vec![(1, 1.23, 1.0)], vec![(2, 2.23, 1.0)]], 2.0),
         (vec![2, 1], 3.46)
     );
     assert_eq!(
         knapsack(
-            vec![vec![(0, 5.0, 9)], vec![(1, 1.23, 10)], vec![(2, 2.23, 10)]],
-            20
+            vec![
+                vec![(0, 5.0, 9.0)],
+                vec![(1, 1.23, 10.0)],
+                vec![(2, 2.23, 10.0)]
+            ],
+            20.0
         ),
         (vec![2, 0], 7.23)
     );
     assert_eq!(
         knapsack(
-            vec![vec![(1, 1.23, 10)], vec![(0, 5.0, 9)], vec![(2, 2.23, 1)]],
-            2
+            vec![
+                vec![(1, 1.23, 10.0)],
+                vec![(0, 5.0, 9.0)],
+                vec![(2, 2.23, 1.0)]
+            ],
+            2.0
+        ),
+        (vec![2], 2.23)
+    );
+    assert_eq!(
+        knapsack(
+            vec![
+                vec![(1, 1.23, 10.0)],
+                vec![(0, 5.0, 9.0)],
+                vec![(2, 2.23, 1.0)]
+            ],
+            10.0
         ),
         (vec![2, 0], 7.23)
     );
     assert_eq!(
         knapsack(
-            vec![vec![(0, 5.0, 9)], vec![(2, 2.23, 10)], vec![(1, 1.23, 10)]],
-            20
+            vec![
+                vec![(0, 5.0, 9.0)],
+                vec![(2, 2.23, 10.0)],
+                vec![(1, 1.23, 10.0)]
+            ],
+            20.0
         ),
         (vec![2, 0], 7.23)
     );
+    // Costs below the old resolution of 10 and a budget that no table could hold
+    assert_eq!(
+        knapsack(
+            vec![
+                vec![(0, 1.0, 3e9 + 4.5), (1, 2.0, 3e9 + 5.5)],
+                vec![(2, 3.0, 7e9 - 4.5)]
+            ],
+            1e10
+        ),
+        (vec![2, 0], 4.0)
+    );
+
+    // Against trying every choice
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    for _ in 0..200 {
+        let items: Vec<Vec<((), f64, f64)>> = (0..6)
+            .map(|_| {
+                (0..rng.gen_range(0..4))
+                    .map(|_| ((), rng.gen_range(-1.0..10.0), rng.gen_range(0.0..10.0)))
+                    .collect()
+            })
+            .collect();
+        let budget = rng.gen_range(0.0..30.0);
+        let mut best = 0.0f64;
+        let choices: usize = items.iter().map(|variants| variants.len() + 1).product();
+        for mut idx in 0..choices {
+            let (mut cost, mut score) = (0.0, 0.0);
+            for variants in &items {
+                if let Some((_, s, c)) = variants.get(idx % (variants.len() + 1)) {
+                    (cost, score) = (cost + c, score + s);
+                }
+                idx /= variants.len() + 1;
+            }
+            if cost <= budget {
+                best = best.max(score);
+            }
+        }
+        let (_, score) = knapsack(items, budget);
+        assert!((score - best).abs() < 1e-9, "{score} != {best}");
+    }
+}
+
+#[test]
+fn test_pareto_front() {
+    let plan = |score, cost| {
+        let submission = CustomerSubmission {
+            months_to_pay_back_loan: 1,
+            yearly_interest_rate: 0.0,
+            awards: Box::new([]),
+        };
+        (("Kim", submission), score, cost)
+    };
+    let plans = [
+        plan(5.0, 20.0),
+        plan(-1.0, 1.0),
+        plan(3.0, 10.0),
+        plan(4.0, 30.0),
+        plan(3.0, 12.0),
+        plan(6.0, 20.0),
+    ];
+    let front = |min_score| -> Vec<_> {
+        pareto_front(plans.clone(), min_score)
+            .iter()
+            .map(|(_, score, cost)| (*score, *cost))
+            .collect()
+    };
+    assert_eq!(front(0.0), [(3.0, 10.0), (6.0, 20.0)]);
+    assert_eq!(
+        front(f64::NEG_INFINITY),
+        [(-1.0, 1.0), (3.0, 10.0), (6.0, 20.0)]
+    );
+}
+
+#[test]
+fn test_portfolio_fits_budget() {
+    let mut indata = crate::test_util::load("Almhult");
+    indata.map.budget = 300_000.0;
+    let (score, submission) =
+        blackbox_locally_optimized_submission(&indata, &OptConfig::preset(Preset::Quick));
+    assert!(!submission.is_empty());
+    let simulated = crate::whitebox::simulate(&indata, &submission).unwrap();
+    assert!((simulated.total_score - score).abs() < 1.0 + 1e-6 * score);
 }
@@ -0,0 +1,177 @@
+use crate::{
+    api::{Api, ApiError, CustomerSubmission, InputData},
+    model::Score,
+    whitebox,
+};
+
+type Submission = Vec<(&'static str, CustomerSubmission)>;
+
+/// Number of whitebox-prefiltered neighbours evaluated with the API per round
+const CANDIDATES_PER_ROUND: usize = 4;
+/// Give up refining once the rate step shrinks below this fraction of the accepted range
+const MIN_RATE_STEP: f64 = 1e-3;
+
+// Hill climbing using real API scores as the objective. Neighbours are ranked by the whitebox
+// and only the most promising ones are sent to the server, so that the call budget is spent
+// where the whitebox and the server are likely to agree on an improvement.
+pub async fn api_refined_submission(
+    api: &Api,
+    indata: &InputData,
+    mut best: Submission,
+    max_calls: usize,
+) -> Result<(Score, Submission), ApiError> {
+    let calls_at_start = api.num_calls();
+    let calls_left = || max_calls.saturating_sub(api.num_calls() - calls_at_start);
+
+    let mut best_score = api.evaluate(indata, &best).await?;
+    tracing::info!(%best_score, "initial");
+
+    let mut rate_step = 0.1;
+    let mut month_step = 4;
+    let mut customers_without_improvement = 0;
+    let mut customer_idx = 0;
+    while calls_left() > 0 && rate_step >= MIN_RATE_STEP && !best.is_empty() {
+        customer_idx %= best.len();
+
+        let mut candidates: Vec<(Score, Submission)> =
+            neighbours(indata, &best, customer_idx, rate_step, month_step)
+                .into_iter()
+                .filter_map(|candidate| {
+                    Some((whitebox::simulate(indata, &candidate).ok()?, candidate))
+                })
+                .collect();
+        candidates.sort_by(|(s1, _), (s2, _)| f64::total_cmp(&s2.total_score, &s1.total_score));
+        candidates.truncate(CANDIDATES_PER_ROUND.min(calls_left()));
+
+        let evaluated = futures::future::join_all(candidates.into_iter().map(
+            |(whitebox_score, candidate)| async move {
+                match api.evaluate(indata, &candidate).await {
+                    Ok(score) => Some((score, whitebox_score, candidate)),
+                    Err(err) => {
+                        tracing::error!(%err, "skipping candidate");
+                        None
+                    }
+                }
+            },
+        ))
+        .await;
+
+        let mut improved = false;
+        for (score, whitebox_score, candidate) in evaluated.into_iter().flatten() {
+            if score.total_score > best_score.total_score {
+                tracing::info!(
+                    %score,
+                    %whitebox_score,
+                    customer = best[customer_idx].0,
+                    calls_left = calls_left(),
+                    "improvement"
+                );
+                best_score = score;
+                best = candidate;
+                improved = true;
+            }
+        }
+
+        if improved {
+            customers_without_improvement = 0;
+        } else {
+            customers_without_improvement += 1;
+            customer_idx += 1;
+        }
+        if customers_without_improvement >= best.len() {
+            rate_step /= 2.0;
+            month_step = (month_step / 2).max(1);
+            customers_without_improvement = 0;
+            tracing::info!(rate_step, month_step, "shrinking step");
+        }
+    }
+    Ok((best_score, best))
+}
+
+fn neighbours(
+    indata: &InputData,
+    submission: &Submission,
+    customer_idx: usize,
+    rate_step: f64,
+    month_step: usize,
+) -> Vec<Submission> {
+    let (name, current) = &submission[customer_idx];
+    let customer = indata
+        .map
+        .customers
+        .iter()
+        .find(|c| c.name == *name)
+        .unwrap();
+    let personality = &indata.personalities[&customer.personality];
+    let rate_range = personality.accepted_max_interest - personality.accepted_min_interest;
+    let months_limit = personality.months_limit_multiplier * indata.map.game_length_in_months;
+
+    let mut variants: Vec<CustomerSubmission> = Vec::new();
+    for sign in [-1.0, 1.0] {
+        let mut variant = current.clone();
+        variant.yearly_interest_rate =
+            (current.yearly_interest_rate + sign * rate_step * rate_range).clamp(
+                personality.accepted_min_interest,
+                personality.accepted_max_interest,
+            );
+        variants.push(variant);
+    }
+    for months in [
+        current.months_to_pay_back_loan.saturating_sub(month_step),
+        current.months_to_pay_back_loan + month_step,
+    ] {
+        let mut variant = current.clone();
+        variant.months_to_pay_back_loan = months.clamp(1, months_limit);
+        variants.push(variant);
+    }
+    // Move each award one month earlier or later
+    for i in 0..current.awards.len() {
+        for j in [i.wrapping_sub(1), i + 1] {
+            if j < current.awards.len()
+                && current.awards[i].is_some()
+                && current.awards[j].is_none()
+            {
+                let mut variant = current.clone();
+                variant.awards.swap(i, j);
+                variants.push(variant);
+            }
+        }
+    }
+
+    variants
+        .into_iter()
+        .filter(|variant| {
+            variant.yearly_interest_rate != current.yearly_interest_rate
+                || variant.months_to_pay_back_loan != current.months_to_pay_back_loan
+                || variant.awards != current.awards
+        })
+        .map(|variant| {
+            let mut candidate = submission.clone();
+            candidate[customer_idx].1 = variant;
+            candidate
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_refinement_respects_call_budget() {
+    use crate::{model::GameRules, test_util};
+
+    let endpoint = test_util::spawn_mock_server(test_util::mock_config(GameRules::default())).await;
+    let indata = test_util::load("Gothenburg");
+    let start = test_util::whole_game_submissions(&indata, test_util::min_rate, |i| {
+        (i % 3 == 2).then_some("IkeaCheck")
+    });
+    let mut best_score = whitebox::simulate(&indata, &start).unwrap().total_score;
+    // A larger budget follows the same climb further
+    for max_calls in [1, 4, 8, 16, 32] {
+        let api = test_util::mock_api(endpoint.clone());
+        let (score, submission) = api_refined_submission(&api, &indata, start.clone(), max_calls)
+            .await
+            .unwrap();
+        assert!(api.num_calls() <= max_calls, "{max_calls}");
+        assert!(score.total_score >= best_score, "{max_calls}");
+        assert_eq!(score, whitebox::simulate(&indata, &submission).unwrap());
+        best_score = score.total_score;
+    }
+}
@@ -0,0 +1,612 @@
+use crate::{
+    api::InputData,
+    model::{Award, Customer, GameRules, Personality},
+};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Per-customer search space. The rate is searched as a unit parameter, see `rate`.
+#[derive(Debug, Clone, Copy)]
+pub struct CustomerProblem<'a> {
+    pub rules: &'a GameRules,
+    pub customer: &'a Customer,
+    pub personality: &'a Personality,
+    pub game_length_in_months: usize,
+    pub award_available: &'a [(&'static str, Award)],
+}
+impl<'a> CustomerProblem<'a> {
+    pub fn new(
+        indata: &'a InputData,
+        customer: &'a Customer,
+        award_available: &'a [(&'static str, Award)],
+    ) -> Self {
+        Self {
+            rules: &indata.rules,
+            customer,
+            personality: &indata.personalities[&customer.personality],
+            game_length_in_months: indata.map.game_length_in_months,
+            award_available,
+        }
+    }
+    pub fn months_limit(&self) -> usize {
+        self.personality.months_limit_multiplier * self.game_length_in_months
+    }
+
+    /// Maps `u` in [0, 1] to the accepted rates, denser near the minimum when the range is huge
+    pub fn rate(&self, u: f64) -> f64 {
+        let u = u.clamp(0.0, 1.0);
+        let u = if self.personality.accepted_max_interest > 1.0 {
+            u.powf(8.0)
+        } else {
+            u
+        };
+        (u * self.personality.accepted_max_interest
+            + (1.0 - u) * self.personality.accepted_min_interest)
+            .clamp(
+                self.personality.accepted_min_interest,
+                self.personality.accepted_max_interest,
+            )
+    }
+
+    /// Simplified kernel score of the customer with the given awards
+    pub fn score(&self, rate: f64, months: usize, awards: &[Option<Award>]) -> f64 {
+        crate::whitebox::simulate_simplified_kernel(
+            self.rules,
+            self.customer,
+            self.personality,
+            rate,
+            months,
+            self.game_length_in_months,
+            awards,
+        )
+        .0
+    }
+
+    /// The objective of the solvers that do not plan awards while searching
+    pub fn score_without_awards(&self, rate: f64, months: usize) -> f64 {
+        self.score(rate, months, &vec![None; self.game_length_in_months])
+    }
+
+    /// Score and bank budget tied up on average over the months, without awards. The peak is
+    /// the loan for almost every (rate, months), but the portfolio is checked month by month,
+    /// so loans paid back sooner leave room for other customers.
+    pub fn score_and_cost_without_awards(&self, rate: f64, months: usize) -> (f64, f64) {
+        let awards = vec![None; self.game_length_in_months];
+        let contract = crate::whitebox::Contract {
+            rules: self.rules,
+            customer: self.customer,
+            personality: self.personality,
+            yearly_interest_rate: rate,
+            months_to_pay_back_loan: months,
+        };
+        let profile = contract.budget_profile(self.game_length_in_months, &awards);
+        let cost = profile.iter().sum::<f64>() / profile.len() as f64;
+        (self.score(rate, months, &awards), cost)
+    }
+}
+
+/// Optimizer of the (rate, months) of a single customer
+pub trait CustomerSolver: Sync {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize);
+
+    /// `solve` followed by up to `n - 1` other (rate, months) trading score for a smaller
+    /// budget, for the portfolio to choose from, see `front_candidates`
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SolverKind {
+    ParticleSwarm,
+    /// Every months value times `grid_rate_steps` rates
+    Grid,
+    /// Golden-section search of the rate for every months value
+    GoldenSection,
+    NelderMead,
+    /// Every months value with its exactly best rate
+    MonthsEnumeration,
+}
+impl SolverKind {
+    pub fn solver(self, config: &crate::opt::OptConfig) -> Box<dyn CustomerSolver> {
+        match self {
+            Self::ParticleSwarm => Box::new(ParticleSwarm {
+                num_particles: config.num_particles,
+                max_iters: config.max_iters,
+                award_conf_ttl: config.award_conf_ttl,
+                seed: config.seed,
+            }),
+            Self::Grid => Box::new(Grid {
+                rate_steps: config.grid_rate_steps,
+            }),
+            Self::GoldenSection => Box::new(GoldenSection { tolerance: 1e-6 }),
+            Self::NelderMead => Box::new(NelderMead {
+                max_iters: config.max_iters,
+            }),
+            Self::MonthsEnumeration => Box::new(MonthsEnumeration),
+        }
+    }
+}
+
+/// `best` followed by the other (rate, months) of `points` on the front of score against budget
+/// tied up, both without awards, from the best down to the cheapest, at most `n` in all.
+///
+/// The budget tied up is the mean of the month by month profile rather than the kernel's
+/// `budget_required`, its peak. The peak is the loan for almost every (rate, months), and the
+/// front against it is the best point alone. Even so the front is mostly short, since interest
+/// grows with the balance left, so the best point usually also ties up the least budget. The
+/// awards, planned for every candidate afterwards, are what trades score for budget for real.
+fn front_candidates(
+    problem: &CustomerProblem<'_>,
+    best: (f64, usize),
+    points: impl IntoIterator<Item = (f64, usize)>,
+    n: usize,
+) -> Vec<(f64, usize)> {
+    let points = points.into_iter().map(|(rate, months)| {
+        let (score, cost) = problem.score_and_cost_without_awards(rate, months);
+        ((rate, months), score, cost)
+    });
+    // Even negative scores, since the missing awards cost a lot of happiness
+    let front = crate::opt::pareto_front(points, f64::NEG_INFINITY);
+    let mut ret = vec![best];
+    ret.extend(
+        front
+            .into_iter()
+            .rev()
+            .map(|(point, _, _)| point)
+            .filter(|&point| point != best),
+    );
+    ret.truncate(n);
+    ret
+}
+
+/// For the solvers searching months and rate together, whose best rate is paired with every
+/// months value since the months mostly decide how long the budget is tied up
+fn best_rate_candidates(
+    problem: &CustomerProblem<'_>,
+    best: (f64, usize),
+    n: usize,
+) -> Vec<(f64, usize)> {
+    let months = 1..=problem.months_limit();
+    front_candidates(problem, best, months.map(|months| (best.0, months)), n)
+}
+
+fn argmax<T: Copy>(candidates: impl Iterator<Item = (f64, T)>) -> T {
+    candidates
+        .max_by(|(s1, _), (s2, _)| f64::total_cmp(s1, s2))
+        .unwrap()
+        .1
+}
+
+pub struct Grid {
+    pub rate_steps: usize,
+}
+impl Grid {
+    fn points<'a>(
+        &'a self,
+        problem: &'a CustomerProblem<'_>,
+    ) -> impl Iterator<Item = (f64, usize)> + 'a {
+        assert!(self.rate_steps >= 2);
+        (1..=problem.months_limit()).flat_map(move |months| {
+            (0..self.rate_steps).map(move |step| {
+                let rate = problem.rate(step as f64 / (self.rate_steps - 1) as f64);
+                (rate, months)
+            })
+        })
+    }
+}
+impl CustomerSolver for Grid {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize) {
+        argmax(
+            self.points(problem)
+                .map(|(rate, months)| (problem.score_without_awards(rate, months), (rate, months))),
+        )
+    }
+
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)> {
+        front_candidates(problem, self.solve(problem), self.points(problem), n)
+    }
+}
+
+/// Assumes the score is unimodal in the rate for fixed months
+pub struct GoldenSection {
+    pub tolerance: f64,
+}
+impl GoldenSection {
+    /// Score and best (rate, months) found for every months value
+    fn best_rates(&self, problem: &CustomerProblem<'_>) -> Vec<(f64, (f64, usize))> {
+        const INV_PHI: f64 = 0.618_033_988_749_895;
+        (1..=problem.months_limit())
+            .map(|months| {
+                let f = |u: f64| problem.score_without_awards(problem.rate(u), months);
+                let (mut lo, mut hi) = (0.0, 1.0);
+                let (mut x1, mut x2) = (hi - INV_PHI * (hi - lo), lo + INV_PHI * (hi - lo));
+                let (mut f1, mut f2) = (f(x1), f(x2));
+                while hi - lo > self.tolerance {
+                    if f1 < f2 {
+                        lo = x1;
+                        (x1, f1) = (x2, f2);
+                        x2 = lo + INV_PHI * (hi - lo);
+                        f2 = f(x2);
+                    } else {
+                        hi = x2;
+                        (x2, f2) = (x1, f1);
+                        x1 = hi - INV_PHI * (hi - lo);
+                        f1 = f(x1);
+                    }
+                }
+                // The ends are not probed by the search
+                let u = argmax([0.0, 1.0, (lo + hi) / 2.0].into_iter().map(|u| (f(u), u)));
+                (f(u), (problem.rate(u), months))
+            })
+            .collect()
+    }
+}
+impl CustomerSolver for GoldenSection {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize) {
+        argmax(self.best_rates(problem).into_iter())
+    }
+
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)> {
+        let best_rates = self.best_rates(problem);
+        let best = argmax(best_rates.iter().copied());
+        front_candidates(problem, best, best_rates.into_iter().map(|(_, p)| p), n)
+    }
+}
+
+pub struct NelderMead {
+    pub max_iters: u64,
+}
+impl CustomerSolver for NelderMead {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize) {
+        // Months are searched as a fraction of the limit and rounded
+        struct Objective<'a>(CustomerProblem<'a>);
+        impl Objective<'_> {
+            fn rate_months(&self, p: &[f64]) -> (f64, usize) {
+                let months_limit = self.0.months_limit();
+                let months =
+                    1 + (p[1].clamp(0.0, 1.0) * (months_limit - 1) as f64).round() as usize;
+                (self.0.rate(p[0]), months)
+            }
+        }
+        impl argmin::core::CostFunction for Objective<'_> {
+            type Param = Vec<f64>;
+            type Output = f64;
+            fn cost(&self, p: &Self::Param) -> Result<Self::Output, argmin::core::Error> {
+                let (rate, months) = self.rate_months(p);
+                Ok(-self.0.score_without_awards(rate, months))
+            }
+        }
+
+        let solver = argmin::solver::neldermead::NelderMead::new(vec![
+            vec![0.5, 0.5],
+            vec![0.9, 0.5],
+            vec![0.5, 0.9],
+        ])
+        .with_sd_tolerance(1e-9)
+        .unwrap();
+        let res = argmin::core::Executor::new(Objective(*problem), solver)
+            .configure(|state| state.max_iters(self.max_iters))
+            .run()
+            .unwrap();
+        res.problem()
+            .problem
+            .as_ref()
+            .unwrap()
+            .rate_months(res.state().best_param.as_ref().unwrap())
+    }
+
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)> {
+        best_rate_candidates(problem, self.solve(problem), n)
+    }
+}
+
+/// Provably best under the simplified kernel without awards. Then the score only depends on the
+/// rate through the interest paid, which grows linearly with the rate as long as the same loan
+/// payments succeed, so the best rate of a months value is the highest one of some set of
+/// payments, see `Contract::same_payments_up_to`.
+pub struct MonthsEnumeration;
+impl MonthsEnumeration {
+    /// Score and best (rate, months) for every months value
+    fn best_rates(problem: &CustomerProblem<'_>) -> Vec<(f64, (f64, usize))> {
+        let (min_rate, max_rate) = (
+            problem.personality.accepted_min_interest,
+            problem.personality.accepted_max_interest,
+        );
+        (1..=problem.months_limit())
+            .map(|months| {
+                let mut best = (f64::NEG_INFINITY, (min_rate, months));
+                let mut rate = min_rate;
+                loop {
+                    let contract = crate::whitebox::Contract {
+                        rules: problem.rules,
+                        customer: problem.customer,
+                        personality: problem.personality,
+                        yearly_interest_rate: rate,
+                        months_to_pay_back_loan: months,
+                    };
+                    let up_to = contract
+                        .same_payments_up_to(problem.game_length_in_months)
+                        .clamp(rate, max_rate);
+                    // Rounding may put `up_to` past the threshold, so also try the start
+                    for rate in [rate, up_to] {
+                        let score = problem.score_without_awards(rate, months);
+                        if score >= best.0 {
+                            best = (score, (rate, months));
+                        }
+                    }
+                    if up_to >= max_rate {
+                        break;
+                    }
+                    rate = up_to.next_up();
+                }
+                best
+            })
+            .collect()
+    }
+}
+impl CustomerSolver for MonthsEnumeration {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize) {
+        argmax(Self::best_rates(problem).into_iter())
+    }
+
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)> {
+        let best_rates = Self::best_rates(problem);
+        let best = argmax(best_rates.iter().copied());
+        front_candidates(problem, best, best_rates.into_iter().map(|(_, p)| p), n)
+    }
+}
+
+/// Also re-plans the awards of every particle with the exact DP every `award_conf_ttl`
+/// evaluations
+pub struct ParticleSwarm {
+    pub num_particles: usize,
+    pub max_iters: u64,
+    pub award_conf_ttl: usize,
+    /// Evaluates particles sequentially when set, so that award re-planning is reproducible
+    pub seed: Option<u64>,
+}
+
+#[derive(Clone)]
+struct Param(Vec<f64>, usize);
+
+impl argmin_math::ArgminAdd<Self, Self> for Param {
+    fn add(&self, other: &Self) -> Self {
+        Self(self.0.add(&other.0), self.1)
+    }
+}
+impl argmin_math::ArgminSub<Self, Self> for Param {
+    fn sub(&self, other: &Self) -> Self {
+        Self(self.0.sub(&other.0), self.1)
+    }
+}
+impl argmin_math::ArgminMinMax for Param {
+    fn min(x: &Self, y: &Self) -> Self {
+        Self(argmin_math::ArgminMinMax::min(&x.0, &y.0), x.1)
+    }
+    fn max(x: &Self, y: &Self) -> Self {
+        Self(argmin_math::ArgminMinMax::max(&x.0, &y.0), x.1)
+    }
+}
+impl argmin_math::ArgminMul<f64, Self> for Param {
+    fn mul(&self, other: &f64) -> Self {
+        Self(self.0.mul(other), self.1)
+    }
+}
+impl argmin_math::ArgminRandom for Param {
+    fn rand_from_range<R: argmin_math::Rng>(min: &Self, max: &Self, rng: &mut R) -> Self {
+        Self(
+            Vec::<f64>::rand_from_range(&min.0, &max.0, rng),
+            u64::rand_from_range(&u64::MIN, &u64::MAX, rng) as usize,
+        )
+    }
+}
+impl argmin_math::ArgminZeroLike for Param {
+    fn zero_like(&self) -> Self {
+        Self(vec![0.0; self.0.len()], self.1)
+    }
+}
+
+impl CustomerSolver for ParticleSwarm {
+    fn solve(&self, problem: &CustomerProblem<'_>) -> (f64, usize) {
+        struct BlackboxOpt<'a> {
+            problem: CustomerProblem<'a>,
+            award_conf_ttl: usize,
+            id_to_awards_ttl: dashmap::DashMap<usize, (AwardConf, usize)>,
+            parallelize: bool,
+        }
+        type AwardConf = Vec<Option<Award>>;
+        fn param_to_rate_months(p: &[f64], problem: &CustomerProblem<'_>) -> (f64, usize) {
+            let months =
+                (p[1] * problem.personality.months_limit_multiplier as f64).round() as usize;
+            (problem.rate(p[0]), months)
+        }
+        impl argmin::core::CostFunction for BlackboxOpt<'_> {
+            type Param = Param;
+            type Output = f64;
+            fn cost(&self, p: &Self::Param) -> Result<Self::Output, argmin::core::Error> {
+                let problem = &self.problem;
+                let (rate, months) = param_to_rate_months(&p.0, problem);
+                let mut entry = self.id_to_awards_ttl.entry(p.1).or_insert_with(|| {
+                    (
+                        vec![None; problem.game_length_in_months],
+                        self.award_conf_ttl,
+                    )
+                });
+                let (awards, ttl) = entry.value_mut();
+                if *ttl == 0 {
+                    let aws = crate::whitebox::simulate_kernel_dp_optimal_awards(
+                        problem.rules,
+                        problem.customer,
+                        problem.personality,
+                        rate,
+                        months,
+                        problem.game_length_in_months,
+                        problem.award_available,
+                    )
+                    .into_iter()
+                    .max_by(|(s1, _, _), (s2, _, _)| f64::total_cmp(s1, s2))
+                    .unwrap_or_else(|| (0.0, 0.0, vec![None; problem.game_length_in_months]));
+
+                    *awards = aws
+                        .2
+                        .into_iter()
+                        .map(|a| a.map(|aa| problem.award_available[(aa.get() as usize) - 1].1))
+                        .collect();
+                    *ttl = self.award_conf_ttl;
+                } else {
+                    *ttl -= 1;
+                }
+                Ok(-problem.score(rate, months, awards))
+            }
+
+            fn parallelize(&self) -> bool {
+                self.parallelize
+            }
+        }
+
+        let opt = BlackboxOpt {
+            problem: *problem,
+            award_conf_ttl: self.award_conf_ttl,
+            id_to_awards_ttl: dashmap::DashMap::new(),
+            parallelize: self.seed.is_none(),
+        };
+        let rng = match self.seed {
+            // Independent of the order in which customers are solved
+            Some(seed) => StdRng::seed_from_u64(
+                problem
+                    .customer
+                    .name
+                    .bytes()
+                    .fold(seed, |h, b| (h ^ b as u64).wrapping_mul(0x100_0000_01b3)),
+            ),
+            None => StdRng::from_entropy(),
+        };
+        let solver = argmin::solver::particleswarm::ParticleSwarm::<Param, f64, _>::new(
+            (
+                Param(vec![0.0, 0.0], 0),
+                Param(vec![1.0, problem.game_length_in_months as f64], usize::MAX),
+            ),
+            self.num_particles,
+        )
+        .with_rng_generator(rng);
+        let res = argmin::core::Executor::new(opt, solver)
+            .configure(|state| state.max_iters(self.max_iters))
+            .run()
+            .unwrap();
+        param_to_rate_months(
+            &res.state().best_individual.as_ref().unwrap().position.0,
+            problem,
+        )
+    }
+
+    fn candidates(&self, problem: &CustomerProblem<'_>, n: usize) -> Vec<(f64, usize)> {
+        best_rate_candidates(problem, self.solve(problem), n)
+    }
+}
+
+#[test]
+fn test_solvers() {
+    let indata = crate::test_util::load("Gothenburg");
+    let award_available = indata.awards_by_id();
+    for customer in &indata.map.customers {
+        let problem = CustomerProblem::new(&indata, customer, &award_available);
+        let (rate, months) = Grid { rate_steps: 200 }.solve(&problem);
+        let solutions = [
+            GoldenSection { tolerance: 1e-6 }.solve(&problem),
+            NelderMead { max_iters: 200 }.solve(&problem),
+            MonthsEnumeration.solve(&problem),
+        ];
+        for (rate, months) in solutions {
+            assert!((1..=problem.months_limit()).contains(&months));
+            assert!(problem.personality.accepted_min_interest <= rate);
+            assert!(rate <= problem.personality.accepted_max_interest);
+        }
+        // Bankruptcy makes the score jump with the rate, otherwise it is unimodal
+        let (grid, _, bankrupt_at) = crate::whitebox::simulate_simplified_kernel(
+            problem.rules,
+            customer,
+            problem.personality,
+            rate,
+            months,
+            problem.game_length_in_months,
+            &vec![None; problem.game_length_in_months],
+        );
+        if bankrupt_at == -1 {
+            let (rate, months) = solutions[0];
+            let golden = problem.score_without_awards(rate, months);
+            assert!(golden >= grid - 1e-6 * grid.abs(), "{golden} < {grid}");
+        }
+        let (rate, months) = solutions[2];
+        let exact = problem.score_without_awards(rate, months);
+        assert!(exact >= grid, "{exact} < {grid}");
+    }
+}
+
+#[test]
+fn test_months_enumeration_beats_fine_grid() {
+    let indata = crate::test_util::load("Nottingham");
+    let award_available = indata.awards_by_id();
+    for customer in &indata.map.customers {
+        let problem = CustomerProblem::new(&indata, customer, &award_available);
+        let score = |(rate, months)| problem.score_without_awards(rate, months);
+        let exact = score(MonthsEnumeration.solve(&problem));
+        let grid = score(Grid { rate_steps: 2000 }.solve(&problem));
+        assert!(exact >= grid, "{}: {exact} < {grid}", customer.name);
+    }
+}
+
+#[test]
+fn test_seeded_particle_swarm_is_reproducible() {
+    let indata = crate::test_util::load("Gothenburg");
+    let award_available = indata.awards_by_id();
+    let customer = &indata.map.customers[1];
+    let problem = CustomerProblem::new(&indata, customer, &award_available);
+    // Re-plans awards with the DP every few evaluations
+    let solver = ParticleSwarm {
+        num_particles: 10,
+        max_iters: 20,
+        award_conf_ttl: 5,
+        seed: Some(7),
+    };
+    assert_eq!(solver.solve(&problem), solver.solve(&problem));
+}
+
+#[test]
+fn test_candidates_trade_score_for_budget() {
+    let indata = crate::test_util::load("Nottingham");
+    let award_available = indata.awards_by_id();
+    let config = crate::opt::OptConfig {
+        num_particles: 10,
+        max_iters: 20,
+        seed: Some(1),
+        ..Default::default()
+    };
+    for kind in [
+        SolverKind::ParticleSwarm,
+        SolverKind::Grid,
+        SolverKind::GoldenSection,
+        SolverKind::NelderMead,
+        SolverKind::MonthsEnumeration,
+    ] {
+        let solver = kind.solver(&config);
+        let mut num_candidates = 0;
+        for customer in &indata.map.customers {
+            let problem = CustomerProblem::new(&indata, customer, &award_available);
+            let candidates = solver.candidates(&problem, 4);
+            assert_eq!(candidates[0], solver.solve(&problem), "{kind:?}");
+            assert!(candidates.len() <= 4);
+            num_candidates += candidates.len();
+            // The others form a front from the best to the cheapest
+            let others: Vec<_> = candidates[1..]
+                .iter()
+                .map(|&(rate, months)| problem.score_and_cost_without_awards(rate, months))
+                .collect();
+            for pair in others.windows(2) {
+                assert!(pair[0].0 > pair[1].0 && pair[0].1 > pair[1].1, "{kind:?}");
+            }
+        }
+        // Interest grows with the balance left, so the best usually also ties up the least
+        // budget, but some customers trade
+        assert!(num_candidates > indata.map.customers.len(), "{kind:?}");
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    api::{Api, CustomerSubmission, InputData, RateLimit},
+    mock::{self, MockConfig},
+    model::{Customer, GameRules, Personality},
+};
+use std::{path::PathBuf, time::Duration};
+use tokio::net::TcpListener;
+
+pub fn data_dir() -> PathBuf {
+    PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../data"))
+}
+
+pub fn load(map_name: &str) -> InputData {
+    InputData::load(&data_dir(), map_name).unwrap()
+}
+
+/// Loan paid back over the whole game at `rate(personality)`, with `award(month)`
+pub fn whole_game_submission(
+    indata: &InputData,
+    customer: &Customer,
+    rate: impl Fn(&Personality) -> f64,
+    award: impl Fn(usize) -> Option<&'static str>,
+) -> (&'static str, CustomerSubmission) {
+    let months_game = indata.map.game_length_in_months;
+    (
+        customer.name,
+        CustomerSubmission {
+            months_to_pay_back_loan: months_game,
+            yearly_interest_rate: rate(&indata.personalities[&customer.personality]),
+            awards: (0..months_game).map(award).collect(),
+        },
+    )
+}
+
+/// `whole_game_submission` of every customer
+pub fn whole_game_submissions(
+    indata: &InputData,
+    rate: impl Fn(&Personality) -> f64,
+    award: impl Fn(usize) -> Option<&'static str>,
+) -> Vec<(&'static str, CustomerSubmission)> {
+    indata
+        .map
+        .customers
+        .iter()
+        .map(|customer| whole_game_submission(indata, customer, &rate, &award))
+        .collect()
+}
+
+pub fn min_rate(personality: &Personality) -> f64 {
+    personality.accepted_min_interest
+}
+
+/// `MockConfig` of the maps in `data_dir()`, responding at once to every call
+pub fn mock_config(rules: GameRules) -> MockConfig {
+    MockConfig {
+        data_dir: data_dir(),
+        rules,
+        min_interval: Duration::ZERO,
+        latency: Duration::ZERO,
+        faults: Vec::new(),
+    }
+}
+
+/// Endpoint of a `mock::serve` spawned on a free port
+pub async fn spawn_mock_server(config: MockConfig) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/game", listener.local_addr().unwrap());
+    tokio::spawn(mock::serve(listener, config));
+    endpoint
+}
+
+/// `Api` of the endpoint without rate limiting
+pub fn mock_api(endpoint: String) -> Api {
+    Api::with_api_key("mock")
+        .with_endpoint(endpoint)
+        .with_rate_limit(RateLimit {
+            interval: Duration::ZERO,
+            burst: 1,
+        })
+}
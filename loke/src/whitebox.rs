@@ -1,303 +1,410 @@
+#[cfg(test)]
+use crate::test_util;
 use crate::{
     api::{CustomerSubmission, InputData},
-    model::{Award, Personality, Score},
-};
-use std::{
-    collections::{BTreeMap, VecDeque},
-    num::{NonZeroU8, NonZeroUsize},
+    model::{Award, Customer, GameRules, Personality, Score},
 };
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{fmt, num::NonZeroU8};
+
+/// Loan terms offered to one customer. The monthly rules live here, and `simulate` and the
+/// kernels all advance a `CustomerState` through them, so that they agree by construction.
+#[derive(Debug, Clone, Copy)]
+pub struct Contract<'a> {
+    pub rules: &'a GameRules,
+    pub customer: &'a Customer,
+    pub personality: &'a Personality,
+    pub yearly_interest_rate: f64,
+    pub months_to_pay_back_loan: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CustomerState {
+    pub capital: f64,
+    pub remaining_balance: f64,
+    pub marks: usize,
+    pub happiness: f64,
+    pub is_bankrupt: bool,
+    pub awards_in_a_row: usize,
+    pub last_award: Option<NonZeroU8>,
+    /// Times `last_award` was given in a row, not counting months without awards
+    pub award_repeats: usize,
+    pub months_without_awards_in_row: usize,
+}
+
+impl Contract<'_> {
+    pub fn initial_state(&self) -> CustomerState {
+        CustomerState {
+            capital: self.customer.capital,
+            remaining_balance: self.customer.loan.amount,
+            marks: 0,
+            happiness: 0.0,
+            is_bankrupt: false,
+            awards_in_a_row: 0,
+            last_award: None,
+            award_repeats: 0,
+            months_without_awards_in_row: 0,
+        }
+    }
+
+    /// Payday, bills and loan payment of month `i`, returning the interest paid to the bank.
+    /// Does not depend on awards, so the loan trajectory is the same for all award choices.
+    pub fn pay(&self, state: &mut CustomerState, i: usize) -> f64 {
+        let Contract {
+            rules,
+            customer,
+            personality,
+            ..
+        } = self;
+
+        // Payday
+        state.capital += customer.income;
+
+        // PayBills
+        let cost_of_monthly_expense =
+            customer.monthly_expenses * personality.living_standard_multiplier;
+        let cost_of_student_loan =
+            if customer.has_student_loan && i.is_multiple_of(rules.student_loan_interval_months) {
+                rules.student_loan_cost
+            } else {
+                0.0
+            };
+        let cost_of_kids = customer.number_of_kids * rules.cost_per_kid;
+        let cost_of_mortgage = customer.home_mortgage * rules.mortgage_factor;
+        state.capital -=
+            cost_of_monthly_expense + cost_of_student_loan + cost_of_kids + cost_of_mortgage;
+
+        // CanPayLoan
+        if i >= self.months_to_pay_back_loan {
+            return 0.0;
+        }
+        let interest_payment = state.remaining_balance * self.yearly_interest_rate / 12.0;
+        let amortization = customer.loan.amount / self.months_to_pay_back_loan as f64;
+        if interest_payment + amortization <= state.capital {
+            // PayLoan
+            state.capital -= interest_payment + amortization;
+            state.remaining_balance = (state.remaining_balance - amortization).max(0.0);
+            interest_payment
+        } else {
+            // IncrementMark
+            state.marks += 1;
+            if state.marks >= rules.marks_limit {
+                state.is_bankrupt = true;
+                state.happiness = rules.bankruptcy_happiness;
+            } else {
+                state.happiness -= rules.mark_happiness_penalty;
+            }
+            0.0
+        }
+    }
+
+    /// Award of the month, returning what it costs the bank
+    // NOTE: They have bug where customer is not paid back interest.
+    pub fn give_award(&self, state: &mut CustomerState, award: Option<&Award>) -> f64 {
+        let rules = self.rules;
+        let Some(award) = award else {
+            state.months_without_awards_in_row += 1;
+            if state.months_without_awards_in_row > rules.no_award_grace_months {
+                state.happiness -=
+                    rules.no_award_penalty_per_month * state.months_without_awards_in_row as f64;
+            }
+            state.awards_in_a_row = state.awards_in_a_row.saturating_sub(1);
+            return 0.0;
+        };
+        if state.last_award == Some(award.id) {
+            state.award_repeats += 1;
+        } else {
+            state.last_award = Some(award.id);
+            state.award_repeats = 1;
+        }
+        state.months_without_awards_in_row = 0;
+
+        let happ_mult = if state.award_repeats >= rules.repeat_award_limit {
+            rules.repeat_happiness_multiplier
+        } else {
+            1.0 - rules.award_fatigue * state.awards_in_a_row as f64
+        };
+        state.happiness += award.base_happiness * self.personality.happiness_multiplier * happ_mult;
+        state.awards_in_a_row = (state.awards_in_a_row + 1).min(rules.max_awards_in_a_row);
+
+        let interest_payment = state.remaining_balance * self.yearly_interest_rate / 12.0;
+        award.cost + award.interest_rebate.unwrap_or(0.0) * interest_payment
+    }
+
+    /// Largest rate at which exactly the same loan payments succeed as at this contract's rate.
+    /// Capital before each payment falls linearly with the rate while the payments stay the
+    /// same, so every successful payment keeps succeeding up to a threshold rate and every
+    /// missed one keeps being missed above the current rate.
+    pub fn same_payments_up_to(&self, months_game: usize) -> f64 {
+        let mut state = self.initial_state();
+        let amortization = self.customer.loan.amount / self.months_to_pay_back_loan as f64;
+        // Interest paid so far per unit of rate, by which the capital falls as the rate grows
+        let mut paid_per_rate = 0.0;
+        let mut up_to = f64::INFINITY;
+        for i in 0..months_game.min(self.months_to_pay_back_loan) {
+            let balance = state.remaining_balance;
+            let marks = state.marks;
+            let interest_payment = self.pay(&mut state, i);
+            if state.marks == marks {
+                // Paid while balance * r / 12 + amortization <= capital - paid_per_rate * (r - rate)
+                let capital = state.capital + interest_payment + amortization;
+                let per_rate = balance / 12.0 + paid_per_rate;
+                if per_rate > 0.0 {
+                    up_to = up_to.min(
+                        (capital + paid_per_rate * self.yearly_interest_rate - amortization)
+                            / per_rate,
+                    );
+                }
+                paid_per_rate += balance / 12.0;
+            }
+            if state.is_bankrupt {
+                break;
+            }
+        }
+        up_to
+    }
+
+    /// Budget tied up by this customer at the checks of every month, from the loan minus the
+    /// interest received plus the awards paid so far. `simulate` checks the budget between
+    /// customers, so it is the larger of the amounts before and after the customer's own month.
+    pub fn budget_profile(&self, months_game: usize, awards: &[Option<Award>]) -> Vec<f64> {
+        let mut state = self.initial_state();
+        let mut shortfall = self.customer.loan.amount;
+        (0..months_game)
+            .map(|i| {
+                let before = shortfall;
+                if !state.is_bankrupt {
+                    shortfall -= self.pay(&mut state, i);
+                    shortfall += self.give_award(&mut state, awards[i].as_ref());
+                }
+                before.max(shortfall)
+            })
+            .collect()
+    }
+
+    /// Month at which the customer goes bankrupt, regardless of awards
+    pub fn bankruptcy_month(&self, months_game: usize) -> Option<usize> {
+        let mut state = self.initial_state();
+        (0..months_game).find(|&i| {
+            self.pay(&mut state, i);
+            state.is_bankrupt
+        })
+    }
+}
 
 pub fn simulate_kernel_dp_optimal_awards(
-    customer: &crate::model::Customer,
+    rules: &GameRules,
+    customer: &Customer,
     personality: &Personality,
     yearly_interest_rate: f64,
     months_to_pay_back_loan: usize,
     months_game: usize,
-    awards_available: &[(&'static str, Award, f64); 6],
+    awards_available: &[(&'static str, Award)],
 ) -> Vec<(f64, f64, Vec<Option<NonZeroU8>>)> {
     // Return pareto optimal (score, cost/max shortfall, award construction)
+    // Paths refer to awards by index + 1, which must be their id
+    assert!(awards_available
+        .iter()
+        .enumerate()
+        .all(|(i, (_, award))| award.id.get() as usize == i + 1));
+    assert!(rules.repeat_award_limit >= 1);
     assert!(personality.accepted_min_interest <= yearly_interest_rate);
     assert!(yearly_interest_rate <= personality.accepted_max_interest);
     assert!(months_to_pay_back_loan <= personality.months_limit_multiplier * months_game);
 
-    let mut ctx = Ctx {
+    let contract = Contract {
+        rules,
         customer,
         personality,
         yearly_interest_rate,
         months_to_pay_back_loan,
-        months_game,
-        awards_available,
-        visited: (0..(2 * 6 * 2 * (months_game + 1)))
-            .map(|_| BTreeMap::default())
-            .collect(),
     };
-    #[derive(Debug)]
-    struct Ctx<'a> {
-        customer: &'a crate::model::Customer,
-        personality: &'a Personality,
-        yearly_interest_rate: f64,
-        months_to_pay_back_loan: usize,
-        months_game: usize,
-        awards_available: &'a [(&'static str, Award, f64); 6],
-        visited: Box<[BTreeMap<TotalFloat, (f64, f64, u16)>]>,
-    }
-    impl<'a> Ctx<'a> {
-        fn partition(
-            &self,
-            force_bankruptcy: bool,
-            i: u16,
-            awards_in_a_row: u8,
-            last_2_awards: [u8; 2],
-        ) -> usize {
-            let mut state = 0;
-            let mut consume = |i: usize, l: usize| {
-                assert!(i < l, "{}", l);
-                state = state * l + i;
-                state
-            };
-            consume(force_bankruptcy as usize, 2);
-            consume(awards_in_a_row as usize, 6);
-            consume((last_2_awards[0] == last_2_awards[1]) as usize, 2);
-            consume(i as usize, self.months_game + 1)
-        }
+    // The loan trajectory does not depend on awards, so all paths go bankrupt in the same month.
+    // Happiness before that is overwritten, so it must not guide the search.
+    let bankruptcy_month = contract.bankruptcy_month(months_game);
+
+    // Forward DP over months. Labels in the same partition agree on everything affecting future
+    // happiness, so a label with no better score, shortfall, required budget and months without
+    // awards than another label of its partition can be dropped.
+    #[derive(Debug, Clone, Copy)]
+    struct Label {
+        state: CustomerState,
+        profit: f64,
+        budget_shortfall: f64,
+        budget_required: f64,
+        /// Last step of the path in `arena`
+        path: u32,
     }
-    let mut results: Vec<_> = [true, false]
+    const ROOT: u32 = u32::MAX;
+    // (previous step, award given)
+    let mut arena: Vec<(u32, Option<NonZeroU8>)> = Vec::new();
+    let score = |label: &Label| {
+        let happiness = if bankruptcy_month.is_some() && !label.state.is_bankrupt {
+            0.0
+        } else {
+            label.state.happiness
+        };
+        customer.loan.environmental_impact + label.profit + happiness
+    };
+    let partition_sizes = [
+        rules.max_awards_in_a_row + 1,
+        awards_available.len() + 1,
+        rules.repeat_award_limit,
+    ];
+    let partition = |state: &CustomerState| {
+        [
+            state.awards_in_a_row,
+            state.last_award.map_or(0, |id| id.get() as usize),
+            // Runs at least one short of the limit are all penalized by the next repeat
+            state.award_repeats.min(rules.repeat_award_limit - 1),
+        ]
         .into_iter()
-        .flat_map(|force_bankruptcy| {
-            dp(
-                &mut ctx,
-                force_bankruptcy,
-                0,
-                customer.capital,
-                customer.loan.amount,
-                0,
-                0,
-                customer.loan.amount,
-                customer.loan.amount,
-                customer.loan.environmental_impact,
-                [0, 0],
-                0,
-            )
+        .zip(partition_sizes)
+        .fold(0, |partition, (i, l)| {
+            assert!(i < l, "{}", l);
+            partition * l + i
         })
-        .collect();
-    #[derive(PartialOrd, PartialEq, Debug)]
-    struct TotalFloat(f64);
-    impl Eq for TotalFloat {}
-    impl Ord for TotalFloat {
-        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-            f64::total_cmp(&self.0, &other.0)
-        }
-    }
-    fn dp<'a>(
-        ctx: &mut Ctx<'a>,
-        force_bankruptcy: bool,            // partition
-        i: u16,                            // partition
-        mut capital: f64,                  // ignore
-        mut remaining_balance: f64,        // ignore
-        mut marks: u8,                     // ignore
-        awards_in_a_row: u8,               // partition
-        mut budget_shortfall: f64,         // prefer lower
-        mut budget_required: f64,          // prefer lower
-        mut score: f64,                    // prefer higher
-        last_2_awards: [u8; 2],            // partition
-        months_without_awards_in_row: u16, // prefer lower
-    ) -> Vec<(f64, f64, Vec<Option<NonZeroU8>>)> {
-        {
-            let p = ctx.partition(force_bankruptcy, i, awards_in_a_row, last_2_awards);
-            if ctx.visited[p].range(TotalFloat(score)..).any(
-                |(_, &(other_shortfall, other_required, other_months_without))| {
-                    other_shortfall <= budget_shortfall
-                        && other_required <= budget_required
-                        && other_months_without <= months_without_awards_in_row
-                },
-            ) {
-                // CULLED
-                return Vec::new();
-            } else {
-                ctx.visited[p].insert(
-                    TotalFloat(score),
-                    (
-                        budget_shortfall,
-                        budget_required,
-                        months_without_awards_in_row,
-                    ),
-                );
+    };
+    fn prune(candidates: &mut Vec<(f64, Label, Option<NonZeroU8>)>) {
+        // Highest score first, so that only earlier labels can dominate later ones
+        candidates.sort_by(|(s1, l1, _), (s2, l2, _)| {
+            f64::total_cmp(s2, s1)
+                .then(f64::total_cmp(&l1.budget_shortfall, &l2.budget_shortfall))
+                .then(f64::total_cmp(&l1.budget_required, &l2.budget_required))
+                .then(
+                    l1.state
+                        .months_without_awards_in_row
+                        .cmp(&l2.state.months_without_awards_in_row),
+                )
+        });
+        // Kept labels by months without awards, as (shortfall, required budget) staircases with
+        // increasing shortfall and decreasing required budget. The step at or before a shortfall
+        // requires the least budget of all kept labels with no more shortfall.
+        let mut staircases: Vec<Vec<(f64, f64)>> = Vec::new();
+        let mut kept: Vec<(f64, Label, Option<NonZeroU8>)> = Vec::new();
+        for candidate in candidates.drain(..) {
+            let (_, label, _) = &candidate;
+            let (shortfall, required) = (label.budget_shortfall, label.budget_required);
+            let months_without_awards = label.state.months_without_awards_in_row;
+            let dominated = staircases
+                .iter()
+                .take(months_without_awards + 1)
+                .any(|staircase| {
+                    let i = staircase.partition_point(|&(s, _)| s <= shortfall);
+                    i > 0 && staircase[i - 1].1 <= required
+                });
+            if dominated {
+                continue;
             }
-        }
-        if (i as usize) == ctx.months_game {
-            if force_bankruptcy || score <= 0.0 {
-                return vec![];
-            } else {
-                return vec![(score, budget_required, Vec::new())];
+            if staircases.len() <= months_without_awards {
+                staircases.resize(months_without_awards + 1, Vec::new());
             }
+            let staircase = &mut staircases[months_without_awards];
+            let i = staircase.partition_point(|&(s, _)| s < shortfall);
+            let covered = staircase[i..].partition_point(|&(_, r)| r >= required);
+            staircase.splice(i..i + covered, [(shortfall, required)]);
+            kept.push(candidate);
         }
-        // PAYMENTS
-        budget_required = budget_required.max(budget_shortfall);
-        let mut is_bankrupt = false;
-        if (i as usize) < ctx.months_to_pay_back_loan {
-            capital += ctx.customer.income;
-            let cost_of_monthly_expense =
-                ctx.customer.monthly_expenses * ctx.personality.living_standard_multiplier;
-            let cost_of_student_loan = if ctx.customer.has_student_loan && (i % 3 == 0) {
-                2000.0
-            } else {
-                0.0
-            };
-            let cost_of_kids = ctx.customer.number_of_kids * 2000.0;
-            let cost_of_mortgage = ctx.customer.home_mortgage * 0.001;
-            capital -=
-                cost_of_monthly_expense + cost_of_student_loan + cost_of_kids + cost_of_mortgage;
-
-            let amortization = ctx.customer.loan.amount / ctx.months_to_pay_back_loan as f64;
-            let interest_payment = remaining_balance * ctx.yearly_interest_rate / 12.0;
-            if interest_payment + amortization <= capital {
-                capital -= interest_payment + amortization;
-                remaining_balance = (remaining_balance - amortization).max(0.0);
-                score += interest_payment;
-                budget_shortfall -= interest_payment;
-            } else {
-                marks += 1;
-                if marks >= 3 {
-                    if force_bankruptcy {
-                        score -= 500.0;
-                        is_bankrupt = true;
-                    } else {
-                        return Vec::new();
-                    }
-                } else {
-                    if force_bankruptcy {
-                    } else {
-                        score -= 50.0;
-                    }
+        *candidates = kept;
+    }
+
+    let mut labels = vec![Label {
+        state: contract.initial_state(),
+        profit: 0.0,
+        budget_shortfall: customer.loan.amount,
+        budget_required: customer.loan.amount,
+        path: ROOT,
+    }];
+    let num_partitions: usize = partition_sizes.iter().product();
+    for i in 0..months_game {
+        let mut partitions = vec![Vec::new(); num_partitions];
+        for label in &labels {
+            // PAYMENTS
+            let mut label = *label;
+            label.budget_required = label.budget_required.max(label.budget_shortfall);
+            let interest_payment = contract.pay(&mut label.state, i);
+            label.profit += interest_payment;
+            label.budget_shortfall -= interest_payment;
+            // AWARDS
+            for award_idx in 0..=awards_available.len() as u8 {
+                let award = NonZeroU8::new(award_idx);
+                let mut next = label;
+                let full_cost = contract.give_award(
+                    &mut next.state,
+                    award.map(|id| &awards_available[id.get() as usize - 1].1),
+                );
+                next.budget_shortfall += full_cost;
+                next.profit -= full_cost;
+                if next.state.is_bankrupt && i + 1 < months_game {
+                    // The bank keeps playing the remaining months with this shortfall
+                    next.budget_required = next.budget_required.max(next.budget_shortfall);
                 }
+                partitions[partition(&next.state)].push((score(&next), next, award));
             }
         }
-        // AWARDS
-        let mut ret = Vec::new();
-        for award_idx in 0..7 {
-            // NOTE: MUST NOT mutate shared vars here:
-            if let Some((_, award, interest_rebate)) =
-                NonZeroUsize::new(award_idx as usize).map(|i| ctx.awards_available[i.get() - 1])
-            {
-                let months_without_awards_in_row = 0;
+        partitions.par_iter_mut().for_each(prune);
+        labels = partitions
+            .into_iter()
+            .flatten()
+            .map(|(_, mut label, award)| {
+                arena.push((label.path, award));
+                label.path = (arena.len() - 1) as u32;
+                label
+            })
+            .collect();
+        if bankruptcy_month == Some(i) {
+            break;
+        }
+    }
 
-                let happ_mult = if last_2_awards[0] == award_idx && last_2_awards[1] == award_idx {
-                    -1.0
-                } else {
-                    1.0 - 0.2 * awards_in_a_row as f64
-                };
-                let last_2_awards = [award_idx, last_2_awards[0]];
-                let score = if is_bankrupt || !force_bankruptcy {
-                    score + award.base_happiness * ctx.personality.happiness_multiplier * happ_mult
-                } else {
-                    score
-                };
-                let interest_payment = remaining_balance * ctx.yearly_interest_rate / 12.0;
-                let full_cost = award.cost + interest_rebate * interest_payment;
-                let budget_shortfall = budget_shortfall + full_cost;
-                let score = score - full_cost;
-                let awards_in_a_row = (awards_in_a_row + 1).min(5);
-
-                if is_bankrupt {
-                    assert!(force_bankruptcy);
-                    if score > 0.0 {
-                        let mut path = vec![None; ctx.months_game - i as usize - 1];
-                        path.push(NonZeroU8::new(award_idx));
-                        ret.push((score, budget_required, path));
-                    }
-                } else {
-                    let mut candidates = dp(
-                        ctx,
-                        force_bankruptcy,
-                        i + 1,
-                        capital,
-                        remaining_balance,
-                        marks,
-                        awards_in_a_row,
-                        budget_shortfall,
-                        budget_required,
-                        score,
-                        last_2_awards,
-                        months_without_awards_in_row,
-                    );
-                    for (_, _, ref mut path) in &mut candidates {
-                        path.push(NonZeroU8::new(award_idx));
-                    }
-                    ret.extend(candidates);
-                }
+    let mut results: Vec<(f64, f64, u32)> = labels
+        .iter()
+        .map(|label| (score(label), label.budget_required, label.path))
+        .filter(|&(score, _, _)| score > 0.0)
+        .collect();
+    // Highest score first
+    results.sort_by(|(s1, _, _), (s2, _, _)| f64::total_cmp(s2, s1));
+    results.retain({
+        let mut lowest_budget_yet = f64::MAX;
+        move |&(_, c, _)| {
+            if c >= lowest_budget_yet {
+                false
             } else {
-                let months_without_awards_in_row = months_without_awards_in_row + 1;
-                let score =
-                    if (is_bankrupt || !force_bankruptcy) && months_without_awards_in_row > 3 {
-                        score - 500.0 * months_without_awards_in_row as f64
-                    } else {
-                        score
-                    };
-                let awards_in_a_row = awards_in_a_row.saturating_sub(1);
-
-                if is_bankrupt {
-                    assert!(force_bankruptcy);
-                    if score > 0.0 {
-                        let mut path = vec![None; ctx.months_game - i as usize - 1];
-                        path.push(NonZeroU8::new(award_idx));
-                        ret.push((score, budget_required, path));
-                    }
-                } else {
-                    let mut candidates = dp(
-                        ctx,
-                        force_bankruptcy,
-                        i + 1,
-                        capital,
-                        remaining_balance,
-                        marks,
-                        awards_in_a_row,
-                        budget_shortfall,
-                        budget_required,
-                        score,
-                        last_2_awards,
-                        months_without_awards_in_row,
-                    );
-                    for (_, _, ref mut path) in &mut candidates {
-                        path.push(NonZeroU8::new(award_idx));
-                    }
-                    ret.extend(candidates);
-                }
+                lowest_budget_yet = c;
+                true
             }
         }
-        // Highest score first
-        ret.sort_by(|(s1, _, _), (s2, _, _)| f64::total_cmp(&s2, &s1));
-        ret.retain({
-            let mut lowest_budget_yet = f64::MAX;
-            move |(_, c, _)| {
-                if *c >= lowest_budget_yet {
-                    false
-                } else {
-                    lowest_budget_yet = *c;
-                    true
-                }
+    });
+    let mut results: Vec<(f64, f64, Vec<Option<NonZeroU8>>)> = results
+        .into_iter()
+        .map(|(score, budget_required, mut step)| {
+            let mut path = Vec::with_capacity(months_game);
+            while step != ROOT {
+                let (previous, award) = arena[step as usize];
+                path.push(award);
+                step = previous;
             }
-        });
-        ret
-    }
+            path.reverse();
+            // Bankrupt paths end in the month of the bankruptcy
+            path.resize(months_game, None);
+            (score, budget_required, path)
+        })
+        .collect();
 
     // CHECKING ANSWER
     for (score, budget_required, path) in &mut results {
-        path.reverse();
-        if true {
+        if cfg!(debug_assertions) {
             let (s, b, _) = simulate_simplified_kernel(
+                rules,
                 customer,
                 personality,
                 yearly_interest_rate,
                 months_to_pay_back_loan,
                 months_game,
                 &path
-                    .into_iter()
-                    .map(|i| {
-                        i.map(|j| {
-                            let t = awards_available[j.get() as usize - 1];
-                            (t.1, t.2)
-                        })
-                    })
+                    .iter_mut()
+                    .map(|i| i.map(|j| awards_available[j.get() as usize - 1].1))
                     .collect::<Vec<_>>(),
             );
             if (*score - s).abs() > 1e-4 || (*budget_required - b).abs() > 1e-4 {
@@ -321,143 +428,207 @@ pub fn simulate_kernel_dp_optimal_awards(
 
 // Maximizing score is equivalent to maximizing this per customer
 pub fn simulate_simplified_kernel(
-    customer: &crate::model::Customer,
+    rules: &GameRules,
+    customer: &Customer,
     personality: &Personality,
     yearly_interest_rate: f64,
     months_to_pay_back_loan: usize,
     months_game: usize,
-    awards_with_rebates: &[Option<(Award, f64)>],
+    awards: &[Option<Award>],
 ) -> (f64, f64, i32) {
     assert!(personality.accepted_min_interest <= yearly_interest_rate);
     assert!(yearly_interest_rate <= personality.accepted_max_interest);
-    assert_eq!(awards_with_rebates.len(), months_game);
+    assert_eq!(awards.len(), months_game);
     assert!(months_to_pay_back_loan <= personality.months_limit_multiplier * months_game);
-    let mut capital: f64 = customer.capital;
-    let mut remaining_balance = customer.loan.amount;
-    let mut marks = 0;
-    let mut awards_in_a_row: i32 = 0;
+    let contract = Contract {
+        rules,
+        customer,
+        personality,
+        yearly_interest_rate,
+        months_to_pay_back_loan,
+    };
+    let mut state = contract.initial_state();
 
     let mut budget_shortfall = customer.loan.amount;
     let mut budget_required = budget_shortfall;
 
     let mut score = customer.loan.environmental_impact;
-    let mut happiness = 0.0;
-    let mut last_3_awards: VecDeque<NonZeroU8> = VecDeque::new();
-    let mut months_without_awards_in_row = 0;
     let mut bankruptcy_at = -1;
-    'bankruptcy: for i in 0..months_game {
+    for (i, award) in awards.iter().enumerate() {
         budget_required = budget_required.max(budget_shortfall);
-        let mut is_bankrupt = false;
-        if i < months_to_pay_back_loan {
-            capital += customer.income;
-            let cost_of_monthly_expense =
-                customer.monthly_expenses * personality.living_standard_multiplier;
-            let cost_of_student_loan = if customer.has_student_loan && (i % 3 == 0) {
-                2000.0
-            } else {
-                0.0
-            };
-            let cost_of_kids = customer.number_of_kids * 2000.0;
-            let cost_of_mortgage = customer.home_mortgage * 0.001;
-            capital -=
-                cost_of_monthly_expense + cost_of_student_loan + cost_of_kids + cost_of_mortgage;
-
-            let amortization = customer.loan.amount / months_to_pay_back_loan as f64;
-            let interest_payment = remaining_balance * yearly_interest_rate / 12.0;
-            if interest_payment + amortization <= capital {
-                capital -= interest_payment + amortization;
-                remaining_balance = (remaining_balance - amortization).max(0.0);
-                score += interest_payment;
-                budget_shortfall -= interest_payment;
-            } else {
-                marks += 1;
-                if marks >= 3 {
-                    happiness = -500.0;
-                    is_bankrupt = true;
-                } else {
-                    happiness -= 50.0;
-                }
+        let interest_payment = contract.pay(&mut state, i);
+        score += interest_payment;
+        budget_shortfall -= interest_payment;
+        let full_cost = contract.give_award(&mut state, award.as_ref());
+        budget_shortfall += full_cost;
+        score -= full_cost;
+        if state.is_bankrupt {
+            // The bank keeps playing the remaining months with this customer's last shortfall
+            if i + 1 < months_game {
+                budget_required = budget_required.max(budget_shortfall);
             }
+            bankruptcy_at = i as i32;
+            break;
         }
-        if let Some((award, interest_rebate)) = awards_with_rebates[i] {
-            while last_3_awards.len() >= 3 {
-                last_3_awards.pop_front();
-            }
-            last_3_awards.push_back(award.id);
-            months_without_awards_in_row = 0;
+    }
+    score += state.happiness;
+    (score, budget_required, bankruptcy_at)
+}
 
-            let happ_mult = if last_3_awards.len() == 3
-                && last_3_awards[0] == last_3_awards[1]
-                && last_3_awards[1] == last_3_awards[2]
-            {
-                -1.0
-            } else {
-                1.0 - 0.2 * awards_in_a_row as f64
-            };
-            happiness += award.base_happiness * personality.happiness_multiplier * happ_mult;
-            let interest_payment = remaining_balance * yearly_interest_rate / 12.0;
-            let full_cost = award.cost + interest_rebate * interest_payment;
-            budget_shortfall += full_cost;
-            score -= full_cost;
-            awards_in_a_row = (awards_in_a_row + 1).min(5);
-        } else {
-            months_without_awards_in_row += 1;
-            if months_without_awards_in_row > 3 {
-                happiness -= 500.0 * months_without_awards_in_row as f64;
+/// State of one customer at the end of one month of `simulate`
+#[derive(Debug, Clone, Serialize)]
+pub struct MonthTrace {
+    pub customer: &'static str,
+    pub month: usize,
+    pub capital: f64,
+    pub remaining_balance: f64,
+    pub marks: usize,
+    pub happiness_delta: f64,
+    pub award: Option<&'static str>,
+    /// Including the interest rebate of the award
+    pub award_cost: f64,
+    pub interest_paid: f64,
+    /// Bank budget after this customer's month
+    pub budget: f64,
+    pub is_bankrupt: bool,
+}
+
+/// Why the server would reject a submission, or the bank going broke while playing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationError {
+    NoCustomers,
+    TooManyMonths {
+        customer: &'static str,
+    },
+    TooFewMonths {
+        customer: &'static str,
+    },
+    UnknownCustomer {
+        customer: &'static str,
+    },
+    UnknownAward {
+        customer: &'static str,
+        award: &'static str,
+    },
+    MonthsLimit {
+        customer: &'static str,
+        months: usize,
+        limit: usize,
+    },
+    /// The bank budget was used up before `month` of the game
+    BudgetExhausted {
+        month: usize,
+    },
+}
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Messages of the server where known
+        match self {
+            SimulationError::NoCustomers => {
+                write!(f, "You must choose at least one customer to play!")
+            }
+            SimulationError::TooManyMonths { .. } => write!(
+                f,
+                "You can not exceed amount of months in 'iterations' then described in map config"
+            ),
+            SimulationError::TooFewMonths { .. } => write!(
+                f,
+                "You must provide customer actions for each month of the designated game length!"
+            ),
+            SimulationError::UnknownCustomer { .. } => {
+                write!(f, "All requested customers must exist on the chosen map!")
+            }
+            SimulationError::UnknownAward { customer, award } => {
+                write!(f, "customer {customer:?} is given unknown award {award:?}")
+            }
+            SimulationError::MonthsLimit {
+                customer,
+                months,
+                limit,
+            } => write!(
+                f,
+                "customer {customer:?} pays back the loan in {months} months, more than the limit of {limit}"
+            ),
+            SimulationError::BudgetExhausted { month } => {
+                write!(f, "Bank ran out of budget in month {month}")
             }
-            awards_in_a_row = (awards_in_a_row - 1).max(0);
-        }
-        if is_bankrupt {
-            bankruptcy_at = i as i32;
-            break 'bankruptcy;
         }
     }
-    score += happiness;
-    (score, budget_required, bankruptcy_at)
 }
+impl std::error::Error for SimulationError {}
 
 pub fn simulate(
     indata: &InputData,
     submission: &[(&'static str, CustomerSubmission)],
-) -> crate::model::Score {
-    assert!(
-        submission.len() > 0,
-        "You must choose at least one customer to play!"
-    );
-
-    assert!(
-        submission
-            .iter()
-            .all(|(_, s)| s.awards.len() <= indata.map.game_length_in_months),
-        "You can not exceed amount of months in 'iterations' then described in map config"
-    );
+) -> Result<Score, SimulationError> {
+    simulate_impl(indata, submission, None)
+}
 
-    assert!(
-        submission
-            .iter()
-            .all(|(_, s)| s.awards.len() == indata.map.game_length_in_months),
-        "You must provide customer actions for each month of the designated game length!"
-    );
+/// Like `simulate`, but also records every customer's state at the end of every month. Months
+/// of bankrupt customers are not recorded, and the trace stops where the bank runs out of budget.
+pub fn simulate_traced(
+    indata: &InputData,
+    submission: &[(&'static str, CustomerSubmission)],
+) -> (Result<Score, SimulationError>, Vec<MonthTrace>) {
+    let mut trace = Vec::new();
+    let score = simulate_impl(indata, submission, Some(&mut trace));
+    (score, trace)
+}
 
-    assert!(
-        submission
-            .iter()
-            .all(|(n, _)| indata.map.customers.iter().any(|c| c.name == *n)),
-        "All requested customers must exist on the chosen map!"
-    );
-    for (name, s) in submission {
-        let customer = indata
-            .map
-            .customers
+pub fn validate(
+    indata: &InputData,
+    submission: &[(&'static str, CustomerSubmission)],
+) -> Result<(), SimulationError> {
+    if submission.is_empty() {
+        return Err(SimulationError::NoCustomers);
+    }
+    let months_game = indata.map.game_length_in_months;
+    if let Some((customer, _)) = submission
+        .iter()
+        .find(|(_, s)| s.awards.len() > months_game)
+    {
+        return Err(SimulationError::TooManyMonths { customer });
+    }
+    if let Some((customer, _)) = submission
+        .iter()
+        .find(|(_, s)| s.awards.len() < months_game)
+    {
+        return Err(SimulationError::TooFewMonths { customer });
+    }
+    for &(name, ref s) in submission {
+        let Some(customer) = indata.map.customers.iter().find(|c| c.name == name) else {
+            return Err(SimulationError::UnknownCustomer { customer: name });
+        };
+        if let Some(award) = s
+            .awards
             .iter()
-            .find(|c| c.name == *name)
-            .unwrap();
+            .flatten()
+            .find(|a| !indata.awards.contains_key(*a))
+        {
+            return Err(SimulationError::UnknownAward {
+                customer: name,
+                award,
+            });
+        }
         let personality = &indata.personalities[&customer.personality];
-        assert!(
-            s.months_to_pay_back_loan
-                <= personality.months_limit_multiplier * indata.map.game_length_in_months
-        );
+        let limit = personality.months_limit_multiplier * months_game;
+        if s.months_to_pay_back_loan > limit {
+            return Err(SimulationError::MonthsLimit {
+                customer: name,
+                months: s.months_to_pay_back_loan,
+                limit,
+            });
+        }
     }
+    Ok(())
+}
+
+fn simulate_impl(
+    indata: &InputData,
+    submission: &[(&'static str, CustomerSubmission)],
+    mut trace: Option<&mut Vec<MonthTrace>>,
+) -> Result<Score, SimulationError> {
+    validate(indata, submission)?;
 
     let accepted_customers: Vec<_> = submission
         .iter()
@@ -501,155 +672,56 @@ pub fn simulate(
             .map(|(c, __)| c.loan.amount)
             .sum::<f64>();
 
-    struct CustomerState {
-        capital: f64,
-        remaining_balance: f64,
-        marks: usize,
-        happiness: f64,
-        is_bankrupt: bool,
-        awards_in_a_row: usize,
-        last_3_awards: VecDeque<NonZeroU8>,
-        months_without_awards_in_row: usize,
-    }
-    let mut customer_state: Vec<CustomerState> = accepted_customers
+    let contracts: Vec<Contract> = accepted_customers
         .iter()
-        .map(|(customer, _customer_submission)| CustomerState {
-            capital: customer.capital,
-            remaining_balance: customer.loan.amount,
-            marks: 0,
-            happiness: 0.0,
-            is_bankrupt: false,
-            awards_in_a_row: 0,
-            last_3_awards: VecDeque::new(),
-            months_without_awards_in_row: 0,
+        .map(|(customer, customer_submission)| Contract {
+            rules: &indata.rules,
+            customer,
+            personality: &indata.personalities[&customer.personality],
+            yearly_interest_rate: customer_submission.yearly_interest_rate,
+            months_to_pay_back_loan: customer_submission.months_to_pay_back_loan,
         })
         .collect();
+    let mut customer_state: Vec<CustomerState> =
+        contracts.iter().map(Contract::initial_state).collect();
     for i in 0..indata.map.game_length_in_months {
-        for ((customer, customer_submission), customer_state) in
-            accepted_customers.iter().zip(customer_state.iter_mut())
+        for (((customer, customer_submission), contract), customer_state) in accepted_customers
+            .iter()
+            .zip(&contracts)
+            .zip(customer_state.iter_mut())
         {
             if budget <= 0.0 {
-                return Score {
-                    map_name: indata.map.name.to_string(),
-
-                    environmental_impact: f64::NEG_INFINITY,
-                    happiness_score: f64::NEG_INFINITY,
-                    total_profit: f64::NEG_INFINITY,
-
-                    total_score: f64::NEG_INFINITY,
-                };
+                return Err(SimulationError::BudgetExhausted { month: i });
             }
             if customer_state.is_bankrupt {
                 continue;
             }
-            let personality = &indata.personalities[&customer.personality];
-
-            // Payday
-            customer_state.capital += customer.income;
+            let happiness_before = customer_state.happiness;
 
-            // PayBills
-            let cost_of_monthly_expense =
-                customer.monthly_expenses * personality.living_standard_multiplier;
-            let cost_of_student_loan = if customer.has_student_loan && (i % 3 == 0) {
-                2000.0
-            } else {
-                0.0
-            };
-            let cost_of_kids = customer.number_of_kids * 2000.0;
-            let cost_of_mortgage = customer.home_mortgage * 0.001;
-            customer_state.capital -=
-                cost_of_monthly_expense + cost_of_student_loan + cost_of_kids + cost_of_mortgage;
-
-            // CanPayLoan
-            if i < customer_submission.months_to_pay_back_loan {
-                let interest_payment = customer_state.remaining_balance
-                    * customer_submission.yearly_interest_rate
-                    / 12.0;
-                let amortization =
-                    customer.loan.amount / customer_submission.months_to_pay_back_loan as f64;
-                /*
-                dbg!(
-                    customer.name,
-                    customer_submission.yearly_interest_rate,
-                    customer_submission.months_to_pay_back_loan,
-                    customer_state.capital,
-                    interest_payment,
-                    amortization,
-                    customer_state.marks,
-                    ret.total_profit
-                );
-                */
-                if interest_payment + amortization <= customer_state.capital {
-                    // PayLoan
-                    customer_state.capital -= interest_payment + amortization;
-                    let profit = interest_payment;
-                    customer_state.remaining_balance =
-                        (customer_state.remaining_balance - amortization).max(0.0);
-                    ret.total_profit += profit;
-                    budget += profit; // NOTE: their bug
-                } else {
-                    // IncrementMark
-                    customer_state.marks += 1;
-                    const MARKS_LIMIT: usize = 3;
-                    if customer_state.marks >= MARKS_LIMIT {
-                        customer_state.is_bankrupt = true;
-                        customer_state.happiness = -500.0;
-                    } else {
-                        customer_state.happiness -= 50.0;
-                    }
-                }
-            }
+            let interest_paid = contract.pay(customer_state, i);
+            ret.total_profit += interest_paid;
+            budget += interest_paid; // NOTE: their bug
 
-            // Award
-            // NOTE: They have bug where customer is not paid back interest.
-            if let Some(award) = customer_submission.awards[i] {
-                let crate::model::Award {
-                    id,
-                    cost,
-                    base_happiness,
-                } = &indata.awards[award];
-
-                while customer_state.last_3_awards.len() >= 3 {
-                    customer_state.last_3_awards.pop_front();
-                }
-                customer_state.last_3_awards.push_back(*id);
-                customer_state.months_without_awards_in_row = 0;
-
-                let happ_mult = if customer_state.last_3_awards.len() == 3
-                    && customer_state.last_3_awards[0] == customer_state.last_3_awards[1]
-                    && customer_state.last_3_awards[1] == customer_state.last_3_awards[2]
-                {
-                    -1.0
-                } else {
-                    1.0 - 0.2 * customer_state.awards_in_a_row as f64
-                };
+            let award = customer_submission.awards[i];
+            let award_cost =
+                contract.give_award(customer_state, award.map(|name| &indata.awards[name]));
+            ret.total_profit -= award_cost;
+            budget -= award_cost;
 
-                customer_state.happiness +=
-                    base_happiness * personality.happiness_multiplier * happ_mult;
-                customer_state.awards_in_a_row = (customer_state.awards_in_a_row + 1).min(5);
-                let cost = *cost
-                    + match award {
-                        "NoInterestRate" => {
-                            customer_state.remaining_balance
-                                * customer_submission.yearly_interest_rate
-                                / 12.0
-                        }
-                        "HalfInterestRate" => {
-                            customer_state.remaining_balance
-                                * customer_submission.yearly_interest_rate
-                                / 24.0
-                        }
-                        _ => 0.0,
-                    };
-                ret.total_profit -= cost;
-                budget -= cost;
-            } else {
-                customer_state.months_without_awards_in_row += 1;
-                if customer_state.months_without_awards_in_row > 3 {
-                    customer_state.happiness -=
-                        500.0 * customer_state.months_without_awards_in_row as f64;
-                }
-                customer_state.awards_in_a_row = customer_state.awards_in_a_row.saturating_sub(1);
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.push(MonthTrace {
+                    customer: customer.name,
+                    month: i,
+                    capital: customer_state.capital,
+                    remaining_balance: customer_state.remaining_balance,
+                    marks: customer_state.marks,
+                    happiness_delta: customer_state.happiness - happiness_before,
+                    award,
+                    award_cost,
+                    interest_paid,
+                    budget,
+                    is_bankrupt: customer_state.is_bankrupt,
+                });
             }
         }
     }
@@ -659,5 +731,266 @@ pub fn simulate(
 
     ret.total_profit = ret.total_profit.trunc();
     ret.total_score = (ret.environmental_impact + ret.happiness_score + ret.total_profit).trunc();
-    ret
+    Ok(ret)
+}
+
+#[test]
+fn test_simulate_traced() {
+    let indata = test_util::load("Gothenburg");
+    let submission = test_util::whole_game_submissions(
+        &indata,
+        |personality| personality.accepted_max_interest,
+        |i| (i % 2 == 0).then_some("IkeaFoodCoupon"),
+    );
+    let (score, trace) = simulate_traced(&indata, &submission);
+    assert_eq!(score, simulate(&indata, &submission));
+    let score = score.unwrap();
+    let happiness: f64 = trace.iter().map(|t| t.happiness_delta).sum();
+    assert!((happiness - score.happiness_score).abs() < 1e-6);
+    let profit: f64 = trace.iter().map(|t| t.interest_paid - t.award_cost).sum();
+    assert!((profit - score.total_profit).abs() < 1.0);
+}
+
+#[test]
+fn test_simulate_errors() {
+    let mut indata = test_util::load("Gothenburg");
+    let customer = &indata.map.customers[0];
+    let (_, valid) =
+        test_util::whole_game_submission(&indata, customer, test_util::min_rate, |_| {
+            Some("IkeaCheck")
+        });
+    let with = |f: &dyn Fn(&mut CustomerSubmission)| {
+        let mut s = valid.clone();
+        f(&mut s);
+        vec![(customer.name, s)]
+    };
+
+    assert_eq!(simulate(&indata, &[]), Err(SimulationError::NoCustomers));
+    assert_eq!(
+        simulate(&indata, &[("Nobody", valid.clone())]),
+        Err(SimulationError::UnknownCustomer { customer: "Nobody" })
+    );
+    assert_eq!(
+        simulate(&indata, &with(&|s| s.awards = s.awards[1..].into())),
+        Err(SimulationError::TooFewMonths {
+            customer: customer.name
+        })
+    );
+    assert_eq!(
+        simulate(&indata, &with(&|s| s.awards[3] = Some("Nothing"))),
+        Err(SimulationError::UnknownAward {
+            customer: customer.name,
+            award: "Nothing"
+        })
+    );
+    assert!(matches!(
+        simulate(&indata, &with(&|s| s.months_to_pay_back_loan = 1000)),
+        Err(SimulationError::MonthsLimit { months: 1000, .. })
+    ));
+
+    let submission = with(&|_| {});
+    assert!(simulate(&indata, &submission).is_ok());
+    indata.map.budget = customer.loan.amount;
+    assert_eq!(
+        simulate(&indata, &submission),
+        Err(SimulationError::BudgetExhausted { month: 0 })
+    );
+}
+
+#[test]
+fn test_kernels_agree_with_simulate() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for map_name in ["Gothenburg", "Nottingham"] {
+        let mut indata = test_util::load(map_name);
+        let months_game = indata.map.game_length_in_months;
+        let awards: Vec<_> = indata.awards.keys().copied().collect();
+        for customer in &indata.map.customers.clone() {
+            let personality = &indata.personalities[&customer.personality];
+            for _ in 0..20 {
+                let award_probability = rng.gen_range(0.0..1.0);
+                let submission = CustomerSubmission {
+                    months_to_pay_back_loan: rng
+                        .gen_range(1..=personality.months_limit_multiplier * months_game),
+                    yearly_interest_rate: rng.gen_range(
+                        personality.accepted_min_interest..=personality.accepted_max_interest,
+                    ),
+                    awards: (0..months_game)
+                        .map(|_| {
+                            rng.gen_bool(award_probability)
+                                .then(|| awards[rng.gen_range(0..awards.len())])
+                        })
+                        .collect(),
+                };
+                let (kernel_score, budget_required, _) = simulate_simplified_kernel(
+                    &indata.rules,
+                    customer,
+                    personality,
+                    submission.yearly_interest_rate,
+                    submission.months_to_pay_back_loan,
+                    months_game,
+                    &submission
+                        .awards
+                        .iter()
+                        .map(|a| a.map(|name| indata.awards[name]))
+                        .collect::<Vec<_>>(),
+                );
+                let submission = [(customer.name, submission)];
+
+                indata.map.budget = budget_required + 1.0;
+                let score = simulate(&indata, &submission).unwrap();
+                assert!((score.total_score - kernel_score).abs() < 2.0);
+                indata.map.budget = budget_required - 1e-3;
+                assert!(matches!(
+                    simulate(&indata, &submission),
+                    Err(SimulationError::BudgetExhausted { .. })
+                ));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dp_kernel_agrees_with_simulate() {
+    let indata = test_util::load("Gothenburg");
+    let months_game = indata.map.game_length_in_months;
+    let awards_available = indata.awards_by_id();
+    let mut num_checked = 0;
+    for customer in &indata.map.customers {
+        let personality = &indata.personalities[&customer.personality];
+        for (rate, months) in [
+            (personality.accepted_min_interest, months_game),
+            (personality.accepted_max_interest, months_game / 2),
+        ] {
+            let results = simulate_kernel_dp_optimal_awards(
+                &indata.rules,
+                customer,
+                personality,
+                rate,
+                months,
+                months_game,
+                &awards_available,
+            );
+            let Some((score, _, path)) = results.first() else {
+                continue;
+            };
+            let submission = [(
+                customer.name,
+                CustomerSubmission {
+                    months_to_pay_back_loan: months,
+                    yearly_interest_rate: rate,
+                    awards: path
+                        .iter()
+                        .map(|a| a.map(|id| awards_available[id.get() as usize - 1].0))
+                        .collect(),
+                },
+            )];
+            let simulated = simulate(&indata, &submission).unwrap();
+            assert!((simulated.total_score - score).abs() < 2.0);
+            num_checked += 1;
+        }
+    }
+    assert!(num_checked > 0);
+}
+
+#[test]
+fn test_rules() {
+    let rules = GameRules::load(&test_util::data_dir().join("Rules-2024.json")).unwrap();
+    assert_eq!(rules, GameRules::default());
+
+    let mut indata = test_util::load("Gothenburg");
+    let customer = &indata.map.customers[0];
+    let submission = [test_util::whole_game_submission(
+        &indata,
+        customer,
+        test_util::min_rate,
+        |_| None,
+    )];
+    let score = simulate(&indata, &submission).unwrap();
+    indata.rules.no_award_grace_months = indata.map.game_length_in_months;
+    let lenient = simulate(&indata, &submission).unwrap();
+    assert_eq!(lenient.total_profit, score.total_profit);
+    assert_eq!(lenient.happiness_score, 0.0);
+    assert!(score.happiness_score < 0.0);
+}
+
+#[test]
+fn test_extra_award() {
+    let mut indata = test_util::load("Gothenburg");
+    indata.awards.insert(
+        "QuarterInterestRate",
+        Award {
+            id: NonZeroU8::new(indata.awards.len() as u8 + 1).unwrap(),
+            cost: 1.0,
+            base_happiness: 100.0,
+            interest_rebate: Some(0.25),
+        },
+    );
+    assert!(indata.validate().is_empty());
+    let customer = &indata.map.customers[0];
+    let rate = indata.personalities[&customer.personality].accepted_min_interest;
+    let submission = [test_util::whole_game_submission(
+        &indata,
+        customer,
+        test_util::min_rate,
+        |_| Some("QuarterInterestRate"),
+    )];
+    let (score, trace) = simulate_traced(&indata, &submission);
+    assert!(score.is_ok());
+    assert_eq!(
+        trace[0].award_cost,
+        1.0 + 0.25 * trace[0].remaining_balance * rate / 12.0
+    );
+}
+
+#[test]
+fn test_dp_kernel_matches_exhaustive_search() {
+    let indata = test_util::load("Gothenburg");
+    let months_game = 6;
+    let awards_available = &indata.awards_by_id()[..3];
+    for repeat_award_limit in [1, 2, 3] {
+        let rules = GameRules {
+            repeat_award_limit,
+            no_award_grace_months: 1,
+            ..GameRules::default()
+        };
+        for customer in &indata.map.customers {
+            let personality = &indata.personalities[&customer.personality];
+            let rate = personality.accepted_min_interest;
+            let dp_best = simulate_kernel_dp_optimal_awards(
+                &rules,
+                customer,
+                personality,
+                rate,
+                months_game,
+                months_game,
+                awards_available,
+            )
+            .first()
+            .map_or(0.0, |&(score, _, _)| score);
+
+            let mut exhaustive_best: f64 = 0.0;
+            for mut path in 0..(awards_available.len() + 1).pow(months_game as u32) {
+                let awards: Vec<_> = (0..months_game)
+                    .map(|_| {
+                        let i = path % (awards_available.len() + 1);
+                        path /= awards_available.len() + 1;
+                        i.checked_sub(1).map(|i| awards_available[i].1)
+                    })
+                    .collect();
+                let (score, _, _) = simulate_simplified_kernel(
+                    &rules,
+                    customer,
+                    personality,
+                    rate,
+                    months_game,
+                    months_game,
+                    &awards,
+                );
+                exhaustive_best = exhaustive_best.max(score);
+            }
+            assert!((dp_best - exhaustive_best).abs() < 1e-6);
+        }
+    }
 }
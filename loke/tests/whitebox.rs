@@ -0,0 +1,243 @@
+use considition2024::{
+    api::{Api, CustomerSubmission, InputData},
+    audit,
+    fixture::{self, Fixture},
+    model::{Customer, Score},
+    test_util,
+    whitebox::{self, MonthTrace},
+};
+use std::{collections::BTreeMap, path::PathBuf};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+const FIXTURES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+type Submission = Vec<(&'static str, CustomerSubmission)>;
+
+// Every customer whose loan fits half the budget, at a low rate over the whole game, with an
+// award every third month
+fn golden_submission(indata: &InputData) -> Submission {
+    let awards = indata.awards_by_id();
+    let mut loans = 0.0;
+    indata
+        .map
+        .customers
+        .iter()
+        .filter(|customer| {
+            loans += customer.loan.amount;
+            loans < indata.map.budget / 2.0
+        })
+        .enumerate()
+        .map(|(j, customer)| {
+            test_util::whole_game_submission(
+                indata,
+                customer,
+                |personality| {
+                    let max_rate = personality.accepted_max_interest.min(1.0);
+                    personality.accepted_min_interest
+                        + 0.1 * (max_rate - personality.accepted_min_interest)
+                },
+                |i| (i % 3 == 2).then(|| awards[(i / 3 + j) % awards.len()].0),
+            )
+        })
+        .collect()
+}
+
+// Pins the whitebox scores of every map. Run with `UPDATE_GOLDEN=1` after intended changes.
+#[test]
+fn golden_scores() {
+    for map_name in ["Almhult", "Gothenburg", "Nottingham"] {
+        let indata = test_util::load(map_name);
+        let score = whitebox::simulate(&indata, &golden_submission(&indata)).unwrap();
+        let path = PathBuf::from(GOLDEN_DIR).join(format!("{map_name}.json"));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+            std::fs::write(&path, serde_json::to_string_pretty(&score).unwrap() + "\n").unwrap();
+        }
+        let golden: Score = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(score, golden, "{map_name}");
+    }
+}
+
+/// Fixtures of `tests/fixtures/*.jsonl` with their `path:line`, failing if there are none
+fn load_fixtures() -> Vec<(String, Fixture)> {
+    let mut fixtures = Vec::new();
+    for entry in std::fs::read_dir(FIXTURES_DIR).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "jsonl") {
+            continue;
+        }
+        for (i, fixture) in fixture::load(&path).unwrap().into_iter().enumerate() {
+            fixtures.push((format!("{}:{}", path.display(), i + 1), fixture));
+        }
+    }
+    assert!(!fixtures.is_empty(), "no fixtures in {FIXTURES_DIR}");
+    fixtures
+}
+
+/// Whitebox score of every fixture's request
+fn simulate_fixtures(fixtures: &[(String, Fixture)]) -> Vec<Score> {
+    let mut maps = BTreeMap::new();
+    fixtures
+        .iter()
+        .map(|(location, fixture)| {
+            let indata = maps
+                .entry(fixture.map_name)
+                .or_insert_with(|| test_util::load(fixture.map_name));
+            whitebox::simulate(indata, &fixture.request.to_per_customer())
+                .unwrap_or_else(|err| panic!("{location}: {err}"))
+        })
+        .collect()
+}
+
+// Replays the responses recorded with `--record-fixtures`. The game server's must agree with the
+// whitebox, while those of a local `mock_server` are the whitebox's own scores and pin them exactly
+#[test]
+fn fixtures_agree_with_endpoint() {
+    let fixtures = load_fixtures();
+    for ((location, fixture), whitebox) in fixtures.iter().zip(simulate_fixtures(&fixtures)) {
+        let recorded = &fixture.response.score;
+        if fixture.endpoint == Api::ENDPOINT {
+            assert!(
+                !audit::diverges(recorded, &whitebox),
+                "{location} ({}): {recorded} != {whitebox}",
+                fixture.response.game_id,
+            );
+        } else {
+            assert_eq!(recorded, &whitebox, "{location} ({})", fixture.endpoint);
+        }
+    }
+}
+
+/// Gothenburg with a single customer derived from its first one
+fn single_customer(edit: impl FnOnce(&mut Customer)) -> InputData {
+    let mut indata = test_util::load("Gothenburg");
+    let mut customer = indata.map.customers[0].clone();
+    customer.personality = "conservative".to_owned();
+    customer.number_of_kids = 0.0;
+    customer.home_mortgage = 0.0;
+    customer.has_student_loan = false;
+    customer.monthly_expenses = 0.0;
+    edit(&mut customer);
+    indata.map.customers = vec![customer];
+    indata
+}
+
+/// Customer who never misses a payment
+fn rich_customer() -> InputData {
+    single_customer(|customer| {
+        customer.capital = 1e9;
+        customer.income = 1e6;
+        customer.loan.amount = 1000.0;
+    })
+}
+
+fn trace_awards(indata: &InputData, awards: &[Option<&'static str>]) -> Vec<MonthTrace> {
+    let customer = &indata.map.customers[0];
+    let submission = [test_util::whole_game_submission(
+        indata,
+        customer,
+        test_util::min_rate,
+        |i| awards.get(i).copied().flatten(),
+    )];
+    let (score, trace) = whitebox::simulate_traced(indata, &submission);
+    score.unwrap();
+    trace
+}
+
+fn award_happiness(indata: &InputData, award: &str) -> f64 {
+    let customer = &indata.map.customers[0];
+    indata.awards[award].base_happiness
+        * indata.personalities[&customer.personality].happiness_multiplier
+}
+
+fn assert_deltas(trace: &[MonthTrace], expected: &[f64]) {
+    for (month, expected) in trace.iter().zip(expected) {
+        assert!(
+            (month.happiness_delta - expected).abs() < 1e-9,
+            "month {}: {} != {expected}",
+            month.month,
+            month.happiness_delta,
+        );
+    }
+}
+
+#[test]
+fn bankruptcy_after_three_marks() {
+    let indata = single_customer(|customer| {
+        customer.capital = 0.0;
+        customer.income = 0.0;
+        customer.loan.amount = 1000.0;
+    });
+    let trace = trace_awards(&indata, &[]);
+    // Nothing is simulated for the customer after going bankrupt
+    assert_eq!(trace.len(), 3);
+    assert_eq!(
+        trace.iter().map(|month| month.marks).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    assert_eq!(
+        trace
+            .iter()
+            .map(|month| month.is_bankrupt)
+            .collect::<Vec<_>>(),
+        [false, false, true]
+    );
+    // Two mark penalties, then happiness is reset rather than penalized further
+    assert_deltas(&trace, &[-50.0, -50.0, -400.0]);
+    assert!(trace.iter().all(|month| month.interest_paid == 0.0));
+}
+
+#[test]
+fn award_fatigue() {
+    let indata = rich_customer();
+    let (a, b) = ("IkeaCheck", "GiftCard");
+    let (ha, hb) = (award_happiness(&indata, a), award_happiness(&indata, b));
+    let trace = trace_awards(
+        &indata,
+        &[
+            Some(a),
+            Some(b),
+            Some(a),
+            Some(b),
+            Some(a),
+            Some(b),
+            None,
+            Some(a),
+        ],
+    );
+    // Every award in a row counts 20% less, down to nothing, and a month without one
+    // forgives one of them
+    assert_deltas(
+        &trace,
+        &[
+            ha,
+            hb * 0.8,
+            ha * 0.6,
+            hb * 0.4,
+            ha * 0.2,
+            0.0,
+            0.0,
+            ha * 0.2,
+        ],
+    );
+}
+
+#[test]
+fn triple_repeat_is_negated() {
+    let indata = rich_customer();
+    let a = "IkeaCheck";
+    let ha = award_happiness(&indata, a);
+    let trace = trace_awards(&indata, &[Some(a), Some(a), Some(a), None, Some(a)]);
+    // Months without awards do not break the repetition
+    assert_deltas(&trace, &[ha, ha * 0.8, -ha, 0.0, -ha]);
+}
+
+#[test]
+fn no_award_penalty() {
+    let indata = rich_customer();
+    let a = "IkeaCheck";
+    let ha = award_happiness(&indata, a);
+    let trace = trace_awards(&indata, &[None, None, None, None, None, Some(a), None]);
+    // Grace for 3 months, then 500 per month without an award so far
+    assert_deltas(&trace, &[0.0, 0.0, 0.0, -2000.0, -2500.0, ha, 0.0]);
+}